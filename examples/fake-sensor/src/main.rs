@@ -59,7 +59,8 @@ async fn run_wasm() -> wasmtime::Result<()> {
     let component =
         unsafe { Component::deserialize_raw(&engine, component_bytes.as_slice().into()) }?;
 
-    let host = ArielOSHost::default();
+    let mut host = ArielOSHost::default();
+    host.set_log_formats(include_bytes!("../payload.logfmt"));
     let mut store = Store::new(&engine, host);
 
     let mut linker = Linker::new(&engine);
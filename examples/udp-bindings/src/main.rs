@@ -5,11 +5,12 @@ use ariel_os::debug::{ExitCode, exit, log::info};
 use ariel_os::time::Timer;
 
 use ariel_os::net;
-use ariel_os::reexports::embassy_net::udp::PacketMetadata;
+use static_cell::ConstStaticCell;
 use wasmtime::component::{Component, HasSelf, Linker, bindgen};
 use wasmtime::{Config, Engine, Store};
 
 use ariel_os_bindings::wasm::ArielOSHost;
+use ariel_os_bindings::wasm::udp::UdpSocketBuffers;
 
 bindgen!({
     world: "example-udp",
@@ -17,10 +18,16 @@ bindgen!({
     with: {
         "ariel:wasm-bindings/log-api": ariel_os_bindings::wasm::log,
         "ariel:wasm-bindings/udp-api": ariel_os_bindings::wasm::udp,
+        "ariel:wasm-bindings/ip-types": ariel_os_bindings::wasm::ip_types,
     },
+    imports: { default: async },
+    exports: { default: async },
 });
 
-static BUFFER_SIZE: usize = 128;
+const BUFFER_SIZE: usize = 128;
+
+static SOCKET_BUFFERS: ConstStaticCell<UdpSocketBuffers<1, BUFFER_SIZE>> =
+    ConstStaticCell::new(UdpSocketBuffers::new());
 
 #[ariel_os::task(autostart)]
 async fn main() {
@@ -48,6 +55,9 @@ async fn run_wasm() -> wasmtime::Result<()> {
     config.max_wasm_stack(2048);
     config.memory_reservation_for_growth(0);
 
+    // Async support
+    config.async_stack_size(4096);
+
     // Use fuel instrumentation to prevent indefinite execution
     config.consume_fuel(true);
 
@@ -61,33 +71,22 @@ async fn run_wasm() -> wasmtime::Result<()> {
 
     let stack = net::network_stack().await.unwrap();
 
-    let mut rx_buffer = [0; BUFFER_SIZE];
-    let mut rx_meta = [PacketMetadata::EMPTY; 1];
-    let mut tx_buffer = [0; BUFFER_SIZE];
-    let mut tx_meta = [PacketMetadata::EMPTY; 1];
-
-    unsafe {
-        host.initialize_socket(
-            stack,
-            &mut rx_meta,
-            &mut rx_buffer,
-            &mut tx_meta,
-            &mut tx_buffer,
-        );
-    }
+    // SAFETY: The store is only used by this task, on the executor running the network stack
+    unsafe { host.add_udp_socket(stack, SOCKET_BUFFERS.take()) };
 
     let mut store = Store::new(&engine, host);
 
-    store.set_fuel(1_000_000)?;
+    // Waiting for packets doesn't consume fuel, only handling them does
+    store.set_fuel(1_000_000_000)?;
+
+    // Yield every 10_000 fuel expanded to let the network stack run
+    store.fuel_async_yield_interval(Some(10_000))?;
 
     let mut linker = Linker::new(&engine);
 
     ExampleUdp::add_to_linker::<_, HasSelf<_>>(&mut linker, |state| state)?;
-    let bindings = ExampleUdp::instantiate(&mut store, &component, &linker)?;
-    bindings.call_bind_socket(&mut store, 1234)?;
-    loop {
-        // This function might never return but it will stop because of fuel exhaustation
-        bindings.call_run(&mut store)?;
-        Timer::after_millis(10).await;
-    }
+    let bindings = ExampleUdp::instantiate_async(&mut store, &component, &linker).await?;
+
+    // This function never returns unless the capsule fails
+    bindings.call_run(&mut store, 1234).await
 }
@@ -10,7 +10,6 @@ static ALLOCATOR: talc::Talck<talc::locking::AssumeUnlockable, talc::ClaimOnOom>
 use wit_bindgen::generate;
 
 extern crate alloc;
-use alloc::format;
 
 generate!({
     world: "example-sensors",
@@ -18,7 +17,6 @@ generate!({
     generate_all,
 });
 
-use ariel::wasm_bindings::log_api::info;
 use ariel::wasm_bindings::sensors_api::*;
 use ariel::wasm_bindings::time_api::sleep;
 
@@ -57,12 +55,14 @@ fn log_measure(sample: Sample, reading_channel: Channel) {
     };
 
     match sample.metadata {
-        SampleMetadata::UnknownAccuracy | SampleMetadata::NoMeasurementError => info(&format!(
-            "[Sensor] {}.{}{}",
-            integer_part,
-            decimal_part,
-            reading_channel.unit.to_str()
-        )),
+        SampleMetadata::UnknownAccuracy | SampleMetadata::NoMeasurementError => {
+            ariel_os_guest_log::info!(
+                "[Sensor] {}.{}{}",
+                integer_part,
+                decimal_part,
+                reading_channel.unit.to_str()
+            )
+        }
         SampleMetadata::SymmetricalError((dev, bias, error_scaling)) => {
             let minus_dev = bias as i32 - dev as i32;
             let plus_dev = bias as i32 + dev as i32;
@@ -85,7 +85,7 @@ fn log_measure(sample: Sample, reading_channel: Channel) {
                 )
             };
 
-            info(&format!(
+            ariel_os_guest_log::info!(
                 "[Sensor] {}.{} +{}.{} -{}.{} {}",
                 integer_part,
                 decimal_part,
@@ -94,10 +94,10 @@ fn log_measure(sample: Sample, reading_channel: Channel) {
                 m_int,
                 m_part,
                 reading_channel.unit.to_str(),
-            ))
+            )
         }
         _ => {
-            info("[Sensor] Error in the reading");
+            ariel_os_guest_log::info!("[Sensor] Error in the reading");
         }
     }
 }
@@ -13,6 +13,8 @@ generate!({
     world: "example-udp",
     path: "../../wit",
     generate_all,
+    // The error enums only implement `std::error::Error` with `std`, which capsules don't have
+    std_feature,
 });
 
 use ariel::wasm_bindings::log_api::info;
@@ -20,24 +22,21 @@ use ariel::wasm_bindings::udp_api::UdpSocket;
 struct MyComponent;
 
 impl Guest for MyComponent {
-    fn bind_socket(port: u16) {
+    fn run(port: u16) {
         info("Hello from inside the capsule");
-        UdpSocket::bind(port).unwrap();
-    }
-
-    fn run() -> () {
-        match UdpSocket::try_recv() {
-            Ok(Some((data, endpoint))) => {
-                info("Received a packet, echoing it back");
-                UdpSocket::send(&data, endpoint).unwrap();
-            }
-            Ok(None) => {
-                // Not packet were ready to be received
-                // info("N");
-            }
-            Err(_) => {
-                info("Something's wrong with the network configuration");
-                panic!()
+        let socket = UdpSocket::bind(port).unwrap();
+        loop {
+            // The host yields to the executor until a packet arrives
+            match socket.recv() {
+                Ok((data, endpoint)) => {
+                    info("Received a packet, echoing it back");
+                    if socket.send(&data, endpoint).is_err() {
+                        info("Could not echo the packet back");
+                    }
+                }
+                Err(_) => {
+                    info("Received a packet too large for the socket buffers");
+                }
             }
         }
     }
@@ -8,6 +8,7 @@ clap = { version = "4.5.40", features = ["derive"]}
 wasmtime = {  git = "https://github.com/bytecodealliance/wasmtime", rev = "3dc6b5ec5572ab8b304c668d5b929bbc7f49cbcf", default-features = false, features = ["component-model", "async", "cranelift", "pulley"] }
 miette = { version = "7.2.0", features = ["fancy"] }
 thiserror = { version = "2.0.12" }
+ariel-os-guest-log = { path = "src/ariel-os-guest-log", features = ["extract"] }
 
 ---
 #![feature(trim_prefix_suffix)]
@@ -20,6 +21,8 @@ use miette::Diagnostic;
 
 use wasmtime::{Config, Engine, OptLevel};
 
+use ariel_os_guest_log::extract::extract_log_formats;
+
 #[derive(Clone, Copy, Debug)]
 enum CLIOptLevel {
     Three,
@@ -97,6 +100,9 @@ enum Error {
 
     #[error("Precompilation Error: {0}")]
     Precomp(#[from] wasmtime::Error),
+
+    #[error("Log format strings: {0}")]
+    LogFormats(#[from] ariel_os_guest_log::extract::ExtractError),
 }
 
 
@@ -196,6 +202,26 @@ fn main() -> miette::Result<()> {
         }
     };
 
+    let out = if output.is_some() {
+        output.unwrap()
+    } else {
+        String::from("payload.cwasm").into()
+    };
+
+    // Move the format strings of deferred log entries out of the payload
+    let wasm = fs::read(&new_path).map_err(Error::from)?;
+    let (stripped, log_formats) = extract_log_formats(&wasm).map_err(Error::from)?;
+    let new_path = if log_formats.is_empty() {
+        new_path
+    } else {
+        let mut formats_path = out.clone();
+        formats_path.set_extension("logfmt");
+        std::println!("Writing {} bytes of log format strings to {}", log_formats.len(), formats_path.display());
+        fs::write(&formats_path, &log_formats).map_err(Error::from)?;
+        fs::write("temp.stripped.wasm", &stripped).map_err(Error::from)?;
+        PathBuf::from("temp.stripped.wasm")
+    };
+
     if !module {
         // Turn into a component
         std::println!("Turning the Module into a component");
@@ -220,12 +246,6 @@ fn main() -> miette::Result<()> {
         }
     }
 
-    let out = if output.is_some() {
-        output.unwrap()
-    } else {
-        String::from("payload.cwasm").into()
-    };
-
     if conserve {
         // Derive the artifacts names from output
         let mut art_path = PathBuf::from(out.clone());
@@ -244,6 +264,9 @@ fn main() -> miette::Result<()> {
     } else {
         precompile(&new_path, &target, fuel, out, module)?;
     }
+    if std::fs::exists("temp.stripped.wasm").map_err(Error::from)? {
+        std::fs::remove_file("temp.stripped.wasm").map_err(Error::from)?;
+    }
     if std::fs::exists("temp").map_err(Error::from)? {
         std::fs::remove_dir_all("temp").map_err(Error::from)?;
     }
@@ -0,0 +1,145 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/adc",
+    path: "../../wit/",
+});
+
+pub use ariel::wasm_bindings::adc_api::{
+    self as gen_adc, Host, HostWithStore, Reading, add_to_linker,
+};
+
+use gen_adc::AdcError;
+
+/// Default upper bound on the number of samples a capsule can average over.
+pub const DEFAULT_MAX_SAMPLES: u16 = 64;
+
+/// A conversion failed, reported to the capsule as `hardware`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct AdcChannelError;
+
+/// An analog input the firmware hands over to a capsule.
+///
+/// This is implemented by the firmware on top of the ADC of its HAL. It must be `Send`, as
+/// wasmtime requires it of the state of stores running capsules asynchronously.
+pub trait AdcChannel: Send {
+    /// Performs a single conversion.
+    fn sample(&mut self) -> Result<u16, AdcChannelError>;
+
+    /// Number of significant bits of the values returned by [`AdcChannel::sample`].
+    fn resolution_bits(&self) -> u8;
+
+    /// Voltage corresponding to a full-scale sample.
+    fn reference_millivolts(&self) -> u32;
+}
+
+struct RegisteredChannel {
+    name: String,
+    channel: Box<dyn AdcChannel>,
+}
+
+impl RegisteredChannel {
+    fn reading(&self, raw: u16) -> Reading {
+        let resolution_bits = self.channel.resolution_bits();
+        let reference_millivolts = self.channel.reference_millivolts();
+        let millivolts = (u64::from(raw) * u64::from(reference_millivolts)) >> resolution_bits;
+        Reading {
+            raw,
+            resolution_bits,
+            reference_millivolts,
+            millivolts: millivolts as u32,
+        }
+    }
+}
+
+pub(crate) struct ArielAdcHost {
+    channels: Vec<RegisteredChannel>,
+    max_samples: u16,
+}
+
+impl Default for ArielAdcHost {
+    fn default() -> Self {
+        Self {
+            channels: Vec::new(),
+            max_samples: DEFAULT_MAX_SAMPLES,
+        }
+    }
+}
+
+impl ArielAdcHost {
+    fn channel(&mut self, name: &str) -> Result<&mut RegisteredChannel, AdcError> {
+        self.channels
+            .iter_mut()
+            .find(|c| c.name == name)
+            .ok_or(AdcError::NoChannel)
+    }
+}
+
+impl Host for ArielAdcHost {
+    fn channels(&mut self) -> Vec<String> {
+        self.channels.iter().map(|c| c.name.clone()).collect()
+    }
+
+    fn sample(&mut self, channel: String) -> Result<Reading, AdcError> {
+        let registered = self.channel(&channel)?;
+        let raw = registered
+            .channel
+            .sample()
+            .map_err(|_| AdcError::Hardware)?;
+        Ok(registered.reading(raw))
+    }
+
+    fn sample_averaged(&mut self, channel: String, samples: u16) -> Result<Reading, AdcError> {
+        if samples == 0 || samples > self.max_samples {
+            return Err(AdcError::InvalidSampleCount);
+        }
+        let registered = self.channel(&channel)?;
+        let mut sum: u32 = 0;
+        for _ in 0..samples {
+            sum += u32::from(
+                registered
+                    .channel
+                    .sample()
+                    .map_err(|_| AdcError::Hardware)?,
+            );
+        }
+        Ok(registered.reading((sum / u32::from(samples)) as u16))
+    }
+}
+
+impl Host for ArielOSHost {
+    fn channels(&mut self) -> Vec<String> {
+        self.adc_host.channels()
+    }
+
+    fn sample(&mut self, channel: String) -> Result<Reading, AdcError> {
+        self.adc_host.sample(channel)
+    }
+
+    fn sample_averaged(&mut self, channel: String, samples: u16) -> Result<Reading, AdcError> {
+        self.adc_host.sample_averaged(channel, samples)
+    }
+}
+
+impl ArielOSHost {
+    /// Registers an analog channel the capsule can sample under `name`.
+    pub fn bind_adc_channel(&mut self, name: &str, channel: impl AdcChannel + 'static) {
+        self.adc_host.channels.retain(|c| c.name != name);
+        self.adc_host.channels.push(RegisteredChannel {
+            name: String::from(name),
+            channel: Box::new(channel),
+        });
+    }
+
+    /// Limits the number of samples a capsule can average over in a single call.
+    pub fn set_adc_max_samples(&mut self, max_samples: u16) {
+        self.adc_host.max_samples = max_samples;
+    }
+}
@@ -0,0 +1,108 @@
+extern crate alloc;
+use alloc::string::String;
+
+use coap_handler::{Handler, Reporting};
+
+use coap_handler_implementations::HandlerBuilder;
+
+use coap_message_utils::Error as CoAPError;
+use coap_message_utils::OptionsExt;
+
+use core::fmt::Write as _;
+
+use crate::wasm::wall_clock::WallClock;
+
+/// Latest time a PUT may set, 2100-01-01T00:00:00Z: anything later is a mistake.
+const MAX_SECONDS: u64 = 4_102_444_800;
+
+/// Serves a [`WallClock`] as `/clock`.
+///
+/// A GET returns the current UTC time as decimal seconds since the Unix epoch, or 4.04 if the
+/// clock was never set. A PUT with such a payload (an integer number of seconds, up to the year
+/// 2100) sets the clock manually, which is the fallback for devices that can't reach an NTP
+/// server.
+pub struct ClockHandler {
+    clock: &'static WallClock,
+}
+
+pub enum ClockRequest {
+    Get,
+    Put,
+}
+
+impl ClockHandler {
+    pub fn new(clock: &'static WallClock) -> Self {
+        Self { clock }
+    }
+
+    pub fn to_handler(self, base: impl Handler + Reporting) -> impl Handler + Reporting {
+        base.at_with_attributes(&["clock"], &[], self)
+    }
+}
+
+impl Handler for ClockHandler {
+    type RequestData = ClockRequest;
+
+    type ExtractRequestError = CoAPError;
+
+    type BuildResponseError<M: coap_message::MinimalWritableMessage> = CoAPError;
+
+    fn extract_request_data<M: coap_message::ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        request.options().ignore_elective_others()?;
+
+        let code: u8 = request.code().into();
+        match code {
+            coap_numbers::code::GET => Ok(ClockRequest::Get),
+            coap_numbers::code::PUT => {
+                let seconds: u64 = core::str::from_utf8(request.payload())
+                    .ok()
+                    .and_then(|s| s.trim().parse().ok())
+                    .filter(|&seconds| seconds <= MAX_SECONDS)
+                    .ok_or_else(CoAPError::bad_request)?;
+                self.clock.set_manually(seconds * 1_000_000);
+                Ok(ClockRequest::Put)
+            }
+            _ => Err(CoAPError::method_not_allowed()),
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        32
+    }
+
+    fn build_response<M: coap_message::MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        use coap_message::Code;
+
+        match request {
+            ClockRequest::Put => {
+                response.set_code(
+                    M::Code::new(coap_numbers::code::CHANGED)
+                        .map_err(CoAPError::from_unionerror)?,
+                );
+            }
+            ClockRequest::Get => {
+                let Some(micros) = self.clock.now_unix_micros() else {
+                    return Err(CoAPError::not_found());
+                };
+                response.set_code(
+                    M::Code::new(coap_numbers::code::CONTENT)
+                        .map_err(CoAPError::from_unionerror)?,
+                );
+                let mut text = String::new();
+                // Writing to a String can't fail
+                let _ = write!(text, "{}.{:06}", micros / 1_000_000, micros % 1_000_000);
+                response
+                    .set_payload(text.as_bytes())
+                    .map_err(CoAPError::from_unionerror)?;
+            }
+        }
+        Ok(())
+    }
+}
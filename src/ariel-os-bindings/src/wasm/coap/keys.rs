@@ -0,0 +1,146 @@
+extern crate alloc;
+use alloc::string::String;
+
+use coap_handler::{Handler, Reporting};
+
+use coap_handler_implementations::HandlerBuilder;
+
+use coap_message::MessageOption;
+
+use coap_message_utils::Error as CoAPError;
+use coap_message_utils::OptionsExt;
+
+use super::sanbdox::StringRef;
+use crate::wasm::crypto::KeyMaterial;
+use crate::wasm::keystore::{KeyStore, algorithm_from_id, algorithm_id};
+
+/// Management endpoint provisioning the keys of a [`KeyStore`] as `/keys/<name>`.
+///
+/// A PUT stores a key, its payload being the identifier of the algorithm (see
+/// [`algorithm_id`]) followed by the secret key material. A DELETE removes the key. A GET only
+/// returns the identifier of the algorithm, key material is never sent back. Key names are not
+/// advertised.
+///
+/// This must only be reachable by the operator, e.g. by restricting it to an authenticated
+/// peer in the CoAP server security configuration.
+pub struct KeyStoreHandler {
+    store: &'static KeyStore,
+}
+
+pub enum KeyRequest {
+    Get(u8),
+    Changed,
+    Deleted,
+}
+
+impl KeyStoreHandler {
+    pub fn new(store: &'static KeyStore) -> Self {
+        Self { store }
+    }
+
+    pub fn to_handler(self, base: impl Handler + Reporting) -> impl Handler + Reporting {
+        base.below(&["keys"], self)
+    }
+}
+
+impl Handler for KeyStoreHandler {
+    type RequestData = KeyRequest;
+
+    type ExtractRequestError = CoAPError;
+
+    type BuildResponseError<M: coap_message::MinimalWritableMessage> = CoAPError;
+
+    fn extract_request_data<M: coap_message::ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        use coap_numbers::option::URI_PATH;
+
+        let mut name: Option<String> = None;
+        request
+            .options()
+            .filter(|o| {
+                if o.number() == URI_PATH
+                    && name.is_none()
+                    && let Some(uri_path) = o.value_str()
+                {
+                    name = Some(String::from(uri_path));
+                    false
+                } else {
+                    true
+                }
+            })
+            .ignore_elective_others()?;
+
+        let Some(name) = name else {
+            return Err(CoAPError::not_found());
+        };
+
+        let code: u8 = request.code().into();
+        match code {
+            coap_numbers::code::GET => self
+                .store
+                .algorithm(&name)
+                .map(|algorithm| KeyRequest::Get(algorithm_id(algorithm)))
+                .ok_or_else(CoAPError::not_found),
+            coap_numbers::code::PUT => {
+                let Some((&id, material)) = request.payload().split_first() else {
+                    return Err(CoAPError::bad_request());
+                };
+                let material = algorithm_from_id(id)
+                    .and_then(|algorithm| KeyMaterial::from_secret_bytes(algorithm, material).ok())
+                    .ok_or_else(CoAPError::bad_request)?;
+                self.store
+                    .store(&name, material)
+                    .map_err(|_| CoAPError::bad_request())?;
+                Ok(KeyRequest::Changed)
+            }
+            coap_numbers::code::DELETE => {
+                self.store.remove(&name);
+                Ok(KeyRequest::Deleted)
+            }
+            _ => Err(CoAPError::method_not_allowed()),
+        }
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        8
+    }
+
+    fn build_response<M: coap_message::MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        use coap_message::Code;
+
+        let code = match request {
+            KeyRequest::Get(_) => coap_numbers::code::CONTENT,
+            KeyRequest::Changed => coap_numbers::code::CHANGED,
+            KeyRequest::Deleted => coap_numbers::code::DELETED,
+        };
+        response.set_code(M::Code::new(code).map_err(CoAPError::from_unionerror)?);
+        if let KeyRequest::Get(id) = request {
+            response
+                .set_payload(&[id])
+                .map_err(CoAPError::from_unionerror)?;
+        }
+        Ok(())
+    }
+}
+
+impl Reporting for KeyStoreHandler {
+    type Record<'res>
+        = StringRef<'res>
+    where
+        Self: 'res;
+
+    type Reporter<'res>
+        = core::iter::Empty<StringRef<'res>>
+    where
+        Self: 'res;
+
+    fn report(&self) -> Self::Reporter<'_> {
+        core::iter::empty()
+    }
+}
@@ -0,0 +1,169 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use coap_handler::{Handler, Reporting};
+
+use coap_handler_implementations::HandlerBuilder;
+use coap_handler_implementations::helpers::block2_write;
+
+use coap_message::MessageOption;
+
+use coap_message_utils::Error as CoAPError;
+use coap_message_utils::OptionsExt;
+use coap_message_utils::option_value::Block2RequestData;
+
+use super::sanbdox::StringRef;
+use crate::wasm::log::LogRing;
+
+/// Serves the log rings of capsules as `/logs/<name>`.
+///
+/// A GET returns the buffered entries as text, one per line, using Block2 for longer contents.
+/// The ETag of the response changes whenever an entry is added, so clients can poll cheaply by
+/// sending it back, and detect that the content moved between two blocks.
+///
+/// Observe is not offered: handlers only ever send a single response, so a registration is
+/// answered like a plain GET, without the Observe option (RFC 7641, Section 4.1), and clients
+/// have to poll with the ETag instead.
+pub struct LogHandler {
+    rings: Vec<(String, &'static LogRing)>,
+}
+
+impl LogHandler {
+    pub fn new() -> Self {
+        Self { rings: Vec::new() }
+    }
+
+    /// Serves `ring` under `/logs/<name>`.
+    pub fn with_ring(mut self, name: &str, ring: &'static LogRing) -> Self {
+        self.rings.push((String::from(name), ring));
+        self
+    }
+
+    pub fn to_handler(self, base: impl Handler + Reporting) -> impl Handler + Reporting {
+        base.below(&["logs"], self)
+    }
+}
+
+impl Default for LogHandler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Handler for LogHandler {
+    // Index of the ring, the Block2 option and whether the client already has this content
+    type RequestData = (usize, Block2RequestData, bool);
+
+    type ExtractRequestError = CoAPError;
+
+    type BuildResponseError<M: coap_message::MinimalWritableMessage> = CoAPError;
+
+    fn extract_request_data<M: coap_message::ReadableMessage>(
+        &mut self,
+        request: &M,
+    ) -> Result<Self::RequestData, Self::ExtractRequestError> {
+        use coap_numbers::option::{BLOCK2, ETAG, URI_PATH};
+
+        let code: u8 = request.code().into();
+        if code != coap_numbers::code::GET {
+            return Err(CoAPError::method_not_allowed());
+        }
+
+        let mut path: Option<String> = None;
+        let mut block2: Option<Block2RequestData> = None;
+        let mut etags: Vec<u32> = Vec::new();
+
+        request
+            .options()
+            .filter(|o| {
+                if o.number() == URI_PATH
+                    && path.is_none()
+                    && let Some(uri_path) = o.value_str()
+                {
+                    path = Some(String::from(uri_path));
+                    false
+                } else if o.number() == BLOCK2
+                    && block2.is_none()
+                    && let Ok(n) = Block2RequestData::from_option(o)
+                {
+                    block2 = Some(n);
+                    false
+                } else if o.number() == ETAG
+                    && let Ok(etag) = <[u8; 4]>::try_from(o.value())
+                {
+                    etags.push(u32::from_be_bytes(etag));
+                    false
+                } else {
+                    true
+                }
+            })
+            .ignore_elective_others()?;
+
+        let Some(path) = path else {
+            return Err(CoAPError::not_found());
+        };
+        let Some(index) = self.rings.iter().position(|(name, _)| *name == path) else {
+            return Err(CoAPError::not_found());
+        };
+        let valid = etags.contains(&self.rings[index].1.sequence());
+
+        Ok((index, block2.unwrap_or_default(), valid))
+    }
+
+    fn estimate_length(&mut self, _request: &Self::RequestData) -> usize {
+        1280 - 40 - 4
+    }
+
+    fn build_response<M: coap_message::MutableWritableMessage>(
+        &mut self,
+        response: &mut M,
+        request: Self::RequestData,
+    ) -> Result<(), Self::BuildResponseError<M>> {
+        use coap_message::{Code, OptionNumber};
+
+        let (index, block2, valid) = request;
+        let ring = self.rings[index].1;
+
+        let code = if valid {
+            coap_numbers::code::VALID
+        } else {
+            coap_numbers::code::CONTENT
+        };
+        response.set_code(M::Code::new(code).map_err(CoAPError::from_unionerror)?);
+        response
+            .add_option(
+                M::OptionNumber::new(coap_numbers::option::ETAG)
+                    .map_err(CoAPError::from_unionerror)?,
+                &ring.sequence().to_be_bytes(),
+            )
+            .map_err(CoAPError::from_unionerror)?;
+
+        if !valid {
+            block2_write(block2, response, |w| {
+                ring.write_to(w)
+                    .map_err(|_| CoAPError::internal_server_error())
+            })?;
+        }
+        Ok(())
+    }
+}
+
+impl Reporting for LogHandler {
+    type Record<'res>
+        = StringRef<'res>
+    where
+        Self: 'res;
+
+    type Reporter<'res>
+        = core::iter::Map<
+        core::slice::Iter<'res, (String, &'static LogRing)>,
+        for<'b> fn(&'b (String, &'static LogRing)) -> StringRef<'b>,
+    >
+    where
+        Self: 'res;
+
+    fn report(&self) -> Self::Reporter<'_> {
+        self.rings.iter().map(|(name, _)| StringRef(name.as_str()))
+    }
+}
@@ -7,3 +7,21 @@ pub mod sanbdox;
 pub use sanbdox::Sandbox;
 
 pub use coap_server_guest::*;
+
+#[cfg(feature = "log-buffer")]
+mod logs;
+
+#[cfg(feature = "log-buffer")]
+pub use logs::LogHandler;
+
+#[cfg(feature = "wall-clock")]
+mod clock;
+
+#[cfg(feature = "wall-clock")]
+pub use clock::ClockHandler;
+
+#[cfg(feature = "keystore")]
+mod keys;
+
+#[cfg(feature = "keystore")]
+pub use keys::KeyStoreHandler;
@@ -0,0 +1,497 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use aes_gcm::aead::generic_array::typenum::Unsigned as _;
+use aes_gcm::aead::{Aead, AeadCore, KeyInit, Payload};
+use aes_gcm::{Aes128Gcm, Aes256Gcm};
+
+use ed25519_dalek::{Signature, Signer as _, SigningKey, VerifyingKey};
+
+use hmac::{Hmac, Mac};
+
+use rand_core::RngCore as _;
+
+use sha2::{Digest as _, Sha256};
+
+use wasmtime::component::{Resource, bindgen};
+
+#[cfg(any(feature = "keystore", feature = "dtls"))]
+use zeroize::Zeroizing;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/crypto",
+    path: "../../wit/",
+});
+
+pub use ariel::wasm_bindings::crypto_api::{
+    self as gen_crypto, Algorithm, CryptoError, Host, HostKey, HostSha256Hasher, HostWithStore,
+    Key, Sha256Hasher, add_to_linker,
+};
+
+type HmacSha256 = Hmac<Sha256>;
+type Aes128Ccm8 = ccm::Ccm<aes::Aes128, ccm::consts::U8, ccm::consts::U13>;
+
+/// Key material held by the host on behalf of a capsule.
+#[derive(Clone)]
+pub enum KeyMaterial {
+    HmacSha256([u8; 32]),
+    Aes128Ccm8([u8; 16]),
+    Aes128Gcm([u8; 16]),
+    Aes256Gcm([u8; 32]),
+    Ed25519Signing(SigningKey),
+    Ed25519(VerifyingKey),
+}
+
+impl KeyMaterial {
+    /// Builds key material of `algorithm` from raw bytes.
+    ///
+    /// For Ed25519 `bytes` is the public key.
+    pub fn from_bytes(algorithm: Algorithm, bytes: &[u8]) -> Result<Self, CryptoError> {
+        let material = match algorithm {
+            Algorithm::HmacSha256 => Self::HmacSha256(exact(bytes)?),
+            Algorithm::Aes128Ccm8 => Self::Aes128Ccm8(exact(bytes)?),
+            Algorithm::Aes128Gcm => Self::Aes128Gcm(exact(bytes)?),
+            Algorithm::Aes256Gcm => Self::Aes256Gcm(exact(bytes)?),
+            Algorithm::Ed25519 => Self::Ed25519(
+                VerifyingKey::from_bytes(&exact(bytes)?).map_err(|_| CryptoError::InvalidKey)?,
+            ),
+        };
+        Ok(material)
+    }
+
+    /// Builds secret key material of `algorithm` from raw bytes.
+    ///
+    /// Unlike [`KeyMaterial::from_bytes`], for Ed25519 `bytes` is the 32-byte secret key, which
+    /// can then sign.
+    pub fn from_secret_bytes(algorithm: Algorithm, bytes: &[u8]) -> Result<Self, CryptoError> {
+        match algorithm {
+            Algorithm::Ed25519 => Ok(Self::Ed25519Signing(SigningKey::from_bytes(&exact(bytes)?))),
+            algorithm => Self::from_bytes(algorithm, bytes),
+        }
+    }
+
+    /// Length of the secret key material of `algorithm`.
+    pub fn secret_len(algorithm: Algorithm) -> usize {
+        match algorithm {
+            Algorithm::Aes128Ccm8 | Algorithm::Aes128Gcm => 16,
+            _ => 32,
+        }
+    }
+
+    /// Raw key material, the inverse of [`KeyMaterial::from_secret_bytes`], and of
+    /// [`KeyMaterial::from_bytes`] for verification-only keys.
+    ///
+    /// The copy is cleared when dropped.
+    #[cfg(any(feature = "keystore", feature = "dtls"))]
+    pub(crate) fn to_bytes(&self) -> Zeroizing<Vec<u8>> {
+        Zeroizing::new(match self {
+            Self::HmacSha256(k) | Self::Aes256Gcm(k) => k.to_vec(),
+            Self::Aes128Ccm8(k) | Self::Aes128Gcm(k) => k.to_vec(),
+            Self::Ed25519Signing(k) => k.to_bytes().to_vec(),
+            Self::Ed25519(k) => k.to_bytes().to_vec(),
+        })
+    }
+
+    fn verifying_key(&self) -> Option<VerifyingKey> {
+        match self {
+            Self::Ed25519Signing(k) => Some(k.verifying_key()),
+            Self::Ed25519(k) => Some(*k),
+            _ => None,
+        }
+    }
+
+    pub fn algorithm(&self) -> Algorithm {
+        match self {
+            Self::HmacSha256(_) => Algorithm::HmacSha256,
+            Self::Aes128Ccm8(_) => Algorithm::Aes128Ccm8,
+            Self::Aes128Gcm(_) => Algorithm::Aes128Gcm,
+            Self::Aes256Gcm(_) => Algorithm::Aes256Gcm,
+            Self::Ed25519Signing(_) | Self::Ed25519(_) => Algorithm::Ed25519,
+        }
+    }
+}
+
+impl Drop for KeyMaterial {
+    fn drop(&mut self) {
+        // Don't leave secrets behind in freed memory
+        let secret: &mut [u8] = match self {
+            Self::HmacSha256(k) | Self::Aes256Gcm(k) => k,
+            Self::Aes128Ccm8(k) | Self::Aes128Gcm(k) => k,
+            // `SigningKey` zeroizes itself
+            Self::Ed25519Signing(_) | Self::Ed25519(_) => return,
+        };
+        for byte in secret.iter_mut() {
+            // SAFETY: `byte` is a valid, aligned and exclusive reference
+            unsafe { core::ptr::write_volatile(byte, 0) };
+        }
+    }
+}
+
+fn exact<const N: usize>(bytes: &[u8]) -> Result<[u8; N], CryptoError> {
+    bytes.try_into().map_err(|_| CryptoError::InvalidKey)
+}
+
+fn seal<A: Aead + KeyInit>(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let cipher = A::new_from_slice(key).map_err(|_| CryptoError::InvalidKey)?;
+    if nonce.len() != <A as AeadCore>::NonceSize::USIZE {
+        return Err(CryptoError::InvalidNonce);
+    }
+    cipher
+        .encrypt(
+            aes_gcm::aead::Nonce::<A>::from_slice(nonce),
+            Payload { msg, aad },
+        )
+        .map_err(|_| CryptoError::InvalidNonce)
+}
+
+fn open<A: Aead + KeyInit>(
+    key: &[u8],
+    nonce: &[u8],
+    aad: &[u8],
+    msg: &[u8],
+) -> Result<Vec<u8>, CryptoError> {
+    let cipher = A::new_from_slice(key).map_err(|_| CryptoError::InvalidKey)?;
+    if nonce.len() != <A as AeadCore>::NonceSize::USIZE {
+        return Err(CryptoError::InvalidNonce);
+    }
+    cipher
+        .decrypt(
+            aes_gcm::aead::Nonce::<A>::from_slice(nonce),
+            Payload { msg, aad },
+        )
+        .map_err(|_| CryptoError::AuthenticationFailed)
+}
+
+/// Keys and hashers of a capsule.
+///
+/// The representation of a [`Key`] resource is its index in `keys`, the one of a
+/// [`Sha256Hasher`] its index in `hashers`.
+#[derive(Default)]
+pub(crate) struct ArielCryptoHost {
+    keys: Vec<Option<KeyMaterial>>,
+    hashers: Vec<Option<Sha256>>,
+    #[cfg(feature = "keystore")]
+    pub(crate) key_store: Option<&'static super::keystore::KeyStore>,
+    /// Names of the keys of the key store the capsule may open.
+    #[cfg(feature = "keystore")]
+    pub(crate) granted_keys: Vec<String>,
+}
+
+/// Stores `value` in the first free slot of `slots` and returns its index.
+fn insert<T>(slots: &mut Vec<Option<T>>, value: T) -> u32 {
+    let index = match slots.iter().position(Option::is_none) {
+        Some(index) => {
+            slots[index] = Some(value);
+            index
+        }
+        None => {
+            slots.push(Some(value));
+            slots.len() - 1
+        }
+    };
+    index as u32
+}
+
+impl ArielCryptoHost {
+    pub(crate) fn add_key(&mut self, material: KeyMaterial) -> Resource<Key> {
+        Resource::new_own(insert(&mut self.keys, material))
+    }
+
+    /// Secret of a symmetric key, for protocols the host runs on behalf of the capsule.
+    #[cfg(feature = "dtls")]
+    pub(crate) fn symmetric_secret(&self, handle: &Resource<Key>) -> Option<Zeroizing<Vec<u8>>> {
+        match self.key(handle) {
+            KeyMaterial::Ed25519Signing(_) | KeyMaterial::Ed25519(_) => None,
+            material => Some(material.to_bytes()),
+        }
+    }
+
+    fn key(&self, handle: &Resource<Key>) -> &KeyMaterial {
+        // Handles only come from `add_key` and are removed on drop
+        self.keys[handle.rep() as usize]
+            .as_ref()
+            .expect("Key used after being dropped")
+    }
+
+    fn hasher(&mut self, handle: &Resource<Sha256Hasher>) -> &mut Sha256 {
+        self.hashers[handle.rep() as usize]
+            .as_mut()
+            .expect("Hasher used after being dropped")
+    }
+}
+
+impl Host for ArielCryptoHost {
+    fn sha256(&mut self, data: Vec<u8>) -> Vec<u8> {
+        Sha256::digest(&data).to_vec()
+    }
+
+    fn hmac_sha256(&mut self, key: Resource<Key>, data: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+        let KeyMaterial::HmacSha256(key) = self.key(&key) else {
+            return Err(CryptoError::WrongAlgorithm);
+        };
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(key).map_err(|_| CryptoError::InvalidKey)?;
+        mac.update(&data);
+        Ok(mac.finalize().into_bytes().to_vec())
+    }
+
+    fn hmac_sha256_verify(
+        &mut self,
+        key: Resource<Key>,
+        data: Vec<u8>,
+        tag: Vec<u8>,
+    ) -> Result<(), CryptoError> {
+        let KeyMaterial::HmacSha256(key) = self.key(&key) else {
+            return Err(CryptoError::WrongAlgorithm);
+        };
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(key).map_err(|_| CryptoError::InvalidKey)?;
+        mac.update(&data);
+        mac.verify_slice(&tag)
+            .map_err(|_| CryptoError::AuthenticationFailed)
+    }
+
+    fn aead_seal(
+        &mut self,
+        key: Resource<Key>,
+        nonce: Vec<u8>,
+        aad: Vec<u8>,
+        plaintext: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        match self.key(&key) {
+            KeyMaterial::Aes128Ccm8(k) => seal::<Aes128Ccm8>(k, &nonce, &aad, &plaintext),
+            KeyMaterial::Aes128Gcm(k) => seal::<Aes128Gcm>(k, &nonce, &aad, &plaintext),
+            KeyMaterial::Aes256Gcm(k) => seal::<Aes256Gcm>(k, &nonce, &aad, &plaintext),
+            _ => Err(CryptoError::WrongAlgorithm),
+        }
+    }
+
+    fn aead_open(
+        &mut self,
+        key: Resource<Key>,
+        nonce: Vec<u8>,
+        aad: Vec<u8>,
+        ciphertext: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        match self.key(&key) {
+            KeyMaterial::Aes128Ccm8(k) => open::<Aes128Ccm8>(k, &nonce, &aad, &ciphertext),
+            KeyMaterial::Aes128Gcm(k) => open::<Aes128Gcm>(k, &nonce, &aad, &ciphertext),
+            KeyMaterial::Aes256Gcm(k) => open::<Aes256Gcm>(k, &nonce, &aad, &ciphertext),
+            _ => Err(CryptoError::WrongAlgorithm),
+        }
+    }
+
+    fn ed25519_sign(
+        &mut self,
+        key: Resource<Key>,
+        message: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        match self.key(&key) {
+            KeyMaterial::Ed25519Signing(key) => Ok(key.sign(&message).to_bytes().to_vec()),
+            KeyMaterial::Ed25519(_) => Err(CryptoError::Unsupported),
+            _ => Err(CryptoError::WrongAlgorithm),
+        }
+    }
+
+    fn ed25519_verify(
+        &mut self,
+        key: Resource<Key>,
+        message: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<(), CryptoError> {
+        let Some(key) = self.key(&key).verifying_key() else {
+            return Err(CryptoError::WrongAlgorithm);
+        };
+        let signature =
+            Signature::from_slice(&signature).map_err(|_| CryptoError::InvalidSignature)?;
+        key.verify_strict(&message, &signature)
+            .map_err(|_| CryptoError::InvalidSignature)
+    }
+}
+
+impl HostKey for ArielCryptoHost {
+    fn generate(&mut self, algorithm: Algorithm) -> Result<Resource<Key>, CryptoError> {
+        let mut bytes = [0u8; 32];
+        let len = KeyMaterial::secret_len(algorithm);
+        ariel_os_random::crypto_rng().fill_bytes(&mut bytes[..len]);
+        let material = KeyMaterial::from_secret_bytes(algorithm, &bytes[..len]);
+        bytes.fill(0);
+        Ok(self.add_key(material?))
+    }
+
+    fn from_public(
+        &mut self,
+        algorithm: Algorithm,
+        public_key: Vec<u8>,
+    ) -> Result<Resource<Key>, CryptoError> {
+        if algorithm != Algorithm::Ed25519 {
+            // Secret keys would have to go through the memory of the capsule
+            return Err(CryptoError::Unsupported);
+        }
+        let material = KeyMaterial::from_bytes(algorithm, &public_key)?;
+        Ok(self.add_key(material))
+    }
+
+    fn open(&mut self, name: String) -> Result<Resource<Key>, CryptoError> {
+        #[cfg(feature = "keystore")]
+        if self.granted_keys.contains(&name)
+            && let Some(material) = self.key_store.and_then(|store| store.get(&name))
+        {
+            return Ok(self.add_key(material));
+        }
+        #[cfg(not(feature = "keystore"))]
+        let _ = name;
+        Err(CryptoError::NotAllowed)
+    }
+
+    fn algorithm(&mut self, self_: Resource<Key>) -> Algorithm {
+        self.key(&self_).algorithm()
+    }
+
+    fn public_key(&mut self, self_: Resource<Key>) -> Result<Vec<u8>, CryptoError> {
+        self.key(&self_)
+            .verifying_key()
+            .map(|key| key.to_bytes().to_vec())
+            .ok_or(CryptoError::WrongAlgorithm)
+    }
+
+    fn drop(&mut self, rep: Resource<Key>) -> wasmtime::Result<()> {
+        if let Some(slot) = self.keys.get_mut(rep.rep() as usize) {
+            *slot = None;
+        }
+        Ok(())
+    }
+}
+
+impl HostSha256Hasher for ArielCryptoHost {
+    fn create(&mut self) -> Resource<Sha256Hasher> {
+        Resource::new_own(insert(&mut self.hashers, Sha256::new()))
+    }
+
+    fn update(&mut self, self_: Resource<Sha256Hasher>, data: Vec<u8>) {
+        self.hasher(&self_).update(&data);
+    }
+
+    fn finalize(&mut self, self_: Resource<Sha256Hasher>) -> Vec<u8> {
+        self.hasher(&self_).finalize_reset().to_vec()
+    }
+
+    fn drop(&mut self, rep: Resource<Sha256Hasher>) -> wasmtime::Result<()> {
+        if let Some(slot) = self.hashers.get_mut(rep.rep() as usize) {
+            *slot = None;
+        }
+        Ok(())
+    }
+}
+
+impl Host for ArielOSHost {
+    fn sha256(&mut self, data: Vec<u8>) -> Vec<u8> {
+        self.crypto_host.sha256(data)
+    }
+
+    fn hmac_sha256(&mut self, key: Resource<Key>, data: Vec<u8>) -> Result<Vec<u8>, CryptoError> {
+        self.crypto_host.hmac_sha256(key, data)
+    }
+
+    fn hmac_sha256_verify(
+        &mut self,
+        key: Resource<Key>,
+        data: Vec<u8>,
+        tag: Vec<u8>,
+    ) -> Result<(), CryptoError> {
+        self.crypto_host.hmac_sha256_verify(key, data, tag)
+    }
+
+    fn aead_seal(
+        &mut self,
+        key: Resource<Key>,
+        nonce: Vec<u8>,
+        aad: Vec<u8>,
+        plaintext: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.crypto_host.aead_seal(key, nonce, aad, plaintext)
+    }
+
+    fn aead_open(
+        &mut self,
+        key: Resource<Key>,
+        nonce: Vec<u8>,
+        aad: Vec<u8>,
+        ciphertext: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.crypto_host.aead_open(key, nonce, aad, ciphertext)
+    }
+
+    fn ed25519_sign(
+        &mut self,
+        key: Resource<Key>,
+        message: Vec<u8>,
+    ) -> Result<Vec<u8>, CryptoError> {
+        self.crypto_host.ed25519_sign(key, message)
+    }
+
+    fn ed25519_verify(
+        &mut self,
+        key: Resource<Key>,
+        message: Vec<u8>,
+        signature: Vec<u8>,
+    ) -> Result<(), CryptoError> {
+        self.crypto_host.ed25519_verify(key, message, signature)
+    }
+}
+
+impl HostKey for ArielOSHost {
+    fn generate(&mut self, algorithm: Algorithm) -> Result<Resource<Key>, CryptoError> {
+        self.crypto_host.generate(algorithm)
+    }
+
+    fn from_public(
+        &mut self,
+        algorithm: Algorithm,
+        public_key: Vec<u8>,
+    ) -> Result<Resource<Key>, CryptoError> {
+        self.crypto_host.from_public(algorithm, public_key)
+    }
+
+    fn open(&mut self, name: String) -> Result<Resource<Key>, CryptoError> {
+        self.crypto_host.open(name)
+    }
+
+    fn algorithm(&mut self, self_: Resource<Key>) -> Algorithm {
+        self.crypto_host.algorithm(self_)
+    }
+
+    fn public_key(&mut self, self_: Resource<Key>) -> Result<Vec<u8>, CryptoError> {
+        self.crypto_host.public_key(self_)
+    }
+
+    fn drop(&mut self, rep: Resource<Key>) -> wasmtime::Result<()> {
+        HostKey::drop(&mut self.crypto_host, rep)
+    }
+}
+
+impl HostSha256Hasher for ArielOSHost {
+    fn create(&mut self) -> Resource<Sha256Hasher> {
+        self.crypto_host.create()
+    }
+
+    fn update(&mut self, self_: Resource<Sha256Hasher>, data: Vec<u8>) {
+        self.crypto_host.update(self_, data)
+    }
+
+    fn finalize(&mut self, self_: Resource<Sha256Hasher>) -> Vec<u8> {
+        self.crypto_host.finalize(self_)
+    }
+
+    fn drop(&mut self, rep: Resource<Sha256Hasher>) -> wasmtime::Result<()> {
+        HostSha256Hasher::drop(&mut self.crypto_host, rep)
+    }
+}
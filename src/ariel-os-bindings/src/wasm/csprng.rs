@@ -0,0 +1,78 @@
+use ariel_os_random::{CryptoRngSend, crypto_rng_send};
+
+extern crate alloc;
+
+use alloc::vec::Vec;
+use rand_core::RngCore as _;
+
+use wasmtime::component::Resource;
+
+use super::ArielOSHost;
+
+mod bindings {
+    // In its own module, as the bindings of the world are named like the [`super::Csprng`] resource
+    wasmtime::component::bindgen!({
+        world: "ariel:wasm-bindings/csprng",
+        path: "../../wit/",
+    });
+}
+
+pub use bindings::ariel::wasm_bindings::csprng_api::{
+    Csprng, CsprngError, Host, HostCsprng, HostWithStore, add_to_linker,
+};
+
+/// Most bytes returned by a single `fill`, larger requests fail.
+const MAX_FILL_LEN: u32 = 256;
+
+/// Crypto-secure RNG of a capsule, kept apart from the fast one of
+/// [`ArielRNGHost`](super::rng::ArielRNGHost).
+pub struct ArielCsprngHost {
+    rng: CryptoRngSend,
+}
+
+impl Default for ArielCsprngHost {
+    fn default() -> Self {
+        Self {
+            rng: crypto_rng_send(),
+        }
+    }
+}
+
+impl Host for ArielCsprngHost {}
+
+impl HostCsprng for ArielCsprngHost {
+    fn fill(&mut self, len: u32) -> Result<Vec<u8>, CsprngError> {
+        if len > MAX_FILL_LEN {
+            return Err(CsprngError::TooLong);
+        }
+        let mut dest: Vec<u8> = core::iter::repeat_n(0, len as usize).collect();
+        self.rng.fill_bytes(&mut dest);
+        Ok(dest)
+    }
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+    fn drop(&mut self, _: Resource<Csprng>) -> wasmtime::Result<()> {
+        unreachable!("Should never be dropped since it's never instantiated")
+    }
+}
+
+impl Host for ArielOSHost {}
+
+impl HostCsprng for ArielOSHost {
+    fn fill(&mut self, len: u32) -> Result<Vec<u8>, CsprngError> {
+        self.csprng_host.fill(len)
+    }
+    fn next_u32(&mut self) -> u32 {
+        self.csprng_host.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.csprng_host.next_u64()
+    }
+    fn drop(&mut self, rep: Resource<Csprng>) -> wasmtime::Result<()> {
+        self.csprng_host.drop(rep)
+    }
+}
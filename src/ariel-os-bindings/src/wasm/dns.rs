@@ -0,0 +1,282 @@
+use ariel_os_embassy::api::time::{Duration, Instant, Timer};
+use ariel_os_embassy::reexports::embassy_net;
+
+use embassy_futures::select::{Either, select};
+
+use embassy_net::IpAddress;
+use embassy_net::dns::{DnsQueryType, Error as DnsQueryError};
+
+use wasmtime::component::bindgen;
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::ArielOSHost;
+use super::ip_types::IpAddr;
+use super::local::Local;
+
+bindgen!({
+    world: "ariel:wasm-bindings/dns",
+    path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+    },
+    imports: {
+        "ariel:wasm-bindings/dns-api.resolve": async,
+    }
+});
+
+pub use ariel::wasm_bindings::dns_api::{
+    self as gen_dns, DnsError, Host, HostWithStore, add_to_linker,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(5);
+const DEFAULT_TTL: Duration = Duration::from_secs(60);
+const DEFAULT_CACHE_SIZE: usize = 4;
+
+struct CacheEntry {
+    name: String,
+    addrs: Vec<IpAddress>,
+    /// `None` for entries added by the host, which never expire.
+    expires: Option<Instant>,
+}
+
+/// Hostname resolution of a capsule.
+///
+/// The network stack doesn't report the TTL of the answers, they are kept for a fixed time
+/// instead. Entries added by the host are never evicted, which also allows running capsules
+/// against names a local DNS stand-in would otherwise provide.
+pub(crate) struct ArielDnsHost {
+    stack: Option<Local<ariel_os_embassy::NetworkStack>>,
+    cache: Vec<CacheEntry>,
+    cache_size: usize,
+    timeout: Duration,
+    ttl: Duration,
+}
+
+impl Default for ArielDnsHost {
+    fn default() -> Self {
+        Self {
+            stack: None,
+            cache: Vec::new(),
+            cache_size: DEFAULT_CACHE_SIZE,
+            timeout: DEFAULT_TIMEOUT,
+            ttl: DEFAULT_TTL,
+        }
+    }
+}
+
+impl From<DnsQueryError> for DnsError {
+    fn from(value: DnsQueryError) -> Self {
+        match value {
+            DnsQueryError::InvalidName | DnsQueryError::NameTooLong => DnsError::InvalidName,
+            DnsQueryError::Failed => DnsError::Failed,
+        }
+    }
+}
+
+impl ArielDnsHost {
+    fn cached(&mut self, name: &str) -> Option<Vec<IpAddress>> {
+        let now = Instant::now();
+        self.cache
+            .retain(|entry| entry.expires.is_none_or(|expires| expires > now));
+        self.cache
+            .iter()
+            .find(|entry| entry.name.eq_ignore_ascii_case(name))
+            .map(|entry| entry.addrs.clone())
+    }
+
+    fn insert(&mut self, name: &str, addrs: Vec<IpAddress>) {
+        let cached = self
+            .cache
+            .iter()
+            .filter(|entry| entry.expires.is_some())
+            .count();
+        if cached >= self.cache_size {
+            // Entries are appended, so the first expiring one is the oldest
+            match self.cache.iter().position(|entry| entry.expires.is_some()) {
+                Some(oldest) => {
+                    self.cache.remove(oldest);
+                }
+                None => return,
+            }
+        }
+        self.cache.push(CacheEntry {
+            name: String::from(name),
+            addrs,
+            expires: Some(Instant::now() + self.ttl),
+        });
+    }
+
+    async fn query(&mut self, name: &str) -> Result<Vec<IpAddress>, DnsError> {
+        let mut stack = self.stack.ok_or(DnsError::Failed)?;
+        let mut addrs = Vec::new();
+        let mut error = None;
+        match stack
+            .run(|stack| stack.dns_query(name, DnsQueryType::A))
+            .await
+        {
+            Ok(found) => addrs.extend(found),
+            Err(err) => error = Some(err),
+        }
+        #[cfg(feature = "ipv6")]
+        match stack
+            .run(|stack| stack.dns_query(name, DnsQueryType::Aaaa))
+            .await
+        {
+            Ok(found) => addrs.extend(found),
+            Err(err) => error = error.or(Some(err)),
+        }
+        match error {
+            // A name with addresses of only one family still resolves
+            _ if !addrs.is_empty() => Ok(addrs),
+            Some(err) => Err(err.into()),
+            None => Err(DnsError::NotFound),
+        }
+    }
+
+    /// Resolves `name` through the cache or the network, for other bindings as well.
+    pub(crate) async fn lookup(&mut self, name: &str) -> Result<Vec<IpAddress>, DnsError> {
+        if name.is_empty() {
+            return Err(DnsError::InvalidName);
+        }
+        if let Some(addrs) = self.cached(name) {
+            return Ok(addrs);
+        }
+        let timeout = Timer::after(self.timeout);
+        let addrs = match select(self.query(name), timeout).await {
+            Either::First(result) => result?,
+            Either::Second(()) => return Err(DnsError::TimedOut),
+        };
+        self.insert(name, addrs.clone());
+        Ok(addrs)
+    }
+}
+
+impl Host for ArielDnsHost {
+    async fn resolve(&mut self, name: String) -> Result<Vec<IpAddr>, DnsError> {
+        let addrs = self.lookup(&name).await?;
+        // Addresses of a family the capsule can't represent are left out
+        Ok(addrs
+            .into_iter()
+            .filter_map(|addr| addr.try_into().ok())
+            .collect())
+    }
+}
+
+impl Host for ArielOSHost {
+    async fn resolve(&mut self, name: String) -> Result<Vec<IpAddr>, DnsError> {
+        self.before_deadline(|host| host.dns_host.resolve(name), Err(DnsError::TimedOut))
+            .await
+    }
+}
+
+impl ArielOSHost {
+    /// Lets the capsule resolve hostnames through the resolvers of `stack`.
+    ///
+    /// # Safety
+    ///
+    /// The host must only be used on the executor running `stack`, queries can't be made from
+    /// other threads or cores.
+    pub unsafe fn bind_dns(&mut self, stack: ariel_os_embassy::NetworkStack) {
+        // SAFETY: Required from the caller
+        self.dns_host.stack = Some(unsafe { Local::new(stack) });
+    }
+
+    /// Configures how long a query may take, and for how long at most `cache_size` answers
+    /// are reused.
+    pub fn configure_dns(&mut self, timeout: Duration, ttl: Duration, cache_size: usize) {
+        self.dns_host.timeout = timeout;
+        self.dns_host.ttl = ttl;
+        self.dns_host.cache_size = cache_size;
+    }
+
+    /// Makes `name` resolve to `addrs` without querying the network.
+    pub fn add_dns_entry(&mut self, name: &str, addrs: &[IpAddress]) {
+        self.dns_host
+            .cache
+            .retain(|entry| !entry.name.eq_ignore_ascii_case(name));
+        self.dns_host.cache.push(CacheEntry {
+            name: String::from(name),
+            addrs: addrs.to_vec(),
+            expires: None,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use embassy_futures::block_on;
+
+    const A: IpAddress = IpAddress::v4(192, 0, 2, 1);
+    const B: IpAddress = IpAddress::v4(192, 0, 2, 2);
+
+    #[test]
+    fn host_entries_override() {
+        let mut host = ArielOSHost::default();
+        host.dns_host.insert("example.org", alloc::vec![A]);
+        host.add_dns_entry("example.org", &[B]);
+        assert_eq!(host.dns_host.cached("example.org"), Some(alloc::vec![B]));
+
+        // Names are case insensitive
+        host.add_dns_entry("Example.ORG", &[A]);
+        assert_eq!(host.dns_host.cached("example.org"), Some(alloc::vec![A]));
+        assert_eq!(host.dns_host.cache.len(), 1);
+    }
+
+    #[test]
+    fn answers_expire() {
+        let mut host = ArielOSHost::default();
+        host.configure_dns(
+            DEFAULT_TIMEOUT,
+            Duration::from_secs(3600),
+            DEFAULT_CACHE_SIZE,
+        );
+        host.dns_host.insert("kept.example", alloc::vec![A]);
+        assert_eq!(host.dns_host.cached("kept.example"), Some(alloc::vec![A]));
+
+        host.configure_dns(DEFAULT_TIMEOUT, Duration::from_secs(0), DEFAULT_CACHE_SIZE);
+        host.dns_host.insert("expired.example", alloc::vec![A]);
+        host.add_dns_entry("host.example", &[B]);
+        assert_eq!(host.dns_host.cached("expired.example"), None);
+        // Entries of the host never expire
+        assert_eq!(host.dns_host.cached("host.example"), Some(alloc::vec![B]));
+    }
+
+    #[test]
+    fn evicts_the_oldest_answer() {
+        let mut host = ArielOSHost::default();
+        host.configure_dns(DEFAULT_TIMEOUT, DEFAULT_TTL, 2);
+        host.add_dns_entry("host.example", &[B]);
+        host.dns_host.insert("first.example", alloc::vec![A]);
+        host.dns_host.insert("second.example", alloc::vec![A]);
+        host.dns_host.insert("third.example", alloc::vec![A]);
+
+        assert_eq!(host.dns_host.cached("first.example"), None);
+        assert!(host.dns_host.cached("second.example").is_some());
+        assert!(host.dns_host.cached("third.example").is_some());
+        assert!(host.dns_host.cached("host.example").is_some());
+    }
+
+    #[test]
+    fn resolves_from_the_cache() {
+        let mut host = ArielOSHost::default();
+        host.add_dns_entry("host.example", &[A, B]);
+        assert_eq!(
+            block_on(host.dns_host.lookup("host.example")),
+            Ok(alloc::vec![A, B])
+        );
+        assert_eq!(
+            block_on(host.dns_host.lookup("")),
+            Err(DnsError::InvalidName)
+        );
+        // Without a stack, only the cached names resolve
+        assert_eq!(
+            block_on(host.dns_host.lookup("other.example")),
+            Err(DnsError::Failed)
+        );
+    }
+}
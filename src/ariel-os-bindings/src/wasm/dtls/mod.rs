@@ -0,0 +1,321 @@
+mod session;
+
+use ariel_os_embassy::api::time::Duration;
+use ariel_os_embassy::reexports::embassy_net::IpEndpoint;
+use ariel_os_embassy::reexports::embassy_net::udp::SendError;
+
+use wasmtime::component::{Resource, bindgen};
+
+use core::task::{Context, Poll, Waker};
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use super::ArielOSHost;
+use super::crypto::Key;
+use super::ip_types::{Endpoint, UnsupportedAddress};
+use super::local::Local;
+use super::net_policy::{NetPolicy, PolicyError};
+use super::udp::gen_udp::UdpError;
+use super::udp::{ArielUDPHost, PooledSocket};
+
+use session::Session;
+
+bindgen!({
+    world: "ariel:wasm-bindings/dtls",
+    path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+        "ariel:wasm-bindings/crypto-api": super::crypto::gen_crypto,
+    },
+    imports: {
+        "ariel:wasm-bindings/dtls-api.[static]dtls-socket.connect": async,
+        "ariel:wasm-bindings/dtls-api.[method]dtls-socket.send": async,
+        "ariel:wasm-bindings/dtls-api.[method]dtls-socket.recv": async,
+    }
+});
+
+pub use ariel::wasm_bindings::dtls_api::{
+    self as gen_dtls, DtlsError, DtlsSocket, Host, HostDtlsSocket, HostWithStore, add_to_linker,
+};
+
+const DEFAULT_HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// An established session and the UDP socket it runs on.
+struct Connection {
+    pooled: PooledSocket,
+    remote: IpEndpoint,
+    session: Session,
+    /// Data received in the same datagram as data already returned.
+    received: VecDeque<Vec<u8>>,
+    closed: bool,
+}
+
+/// DTLS sessions of a capsule.
+///
+/// Sessions use the sockets of the UDP host and are subject to its policy. The representation
+/// of a [`DtlsSocket`] resource is the index of the session in `connections`.
+pub(crate) struct ArielDtlsHost {
+    connections: Vec<Option<Connection>>,
+    /// Socket of the handshake in progress.
+    ///
+    /// It stays here until the handshake completes, so a handshake cut short by the deadline of
+    /// the capsule doesn't lose it: the next `connect` returns it to the UDP host.
+    handshaking: Option<PooledSocket>,
+    handshake_timeout: Duration,
+}
+
+impl Default for ArielDtlsHost {
+    fn default() -> Self {
+        Self {
+            connections: Vec::new(),
+            handshaking: None,
+            handshake_timeout: DEFAULT_HANDSHAKE_TIMEOUT,
+        }
+    }
+}
+
+impl From<UdpError> for DtlsError {
+    fn from(value: UdpError) -> Self {
+        match value {
+            UdpError::NoBuffers => DtlsError::NoBuffers,
+            UdpError::PacketTooLarge => DtlsError::PacketTooLarge,
+            UdpError::UnsupportedAddress => DtlsError::UnsupportedAddress,
+            UdpError::PermissionDenied => DtlsError::PermissionDenied,
+            UdpError::RateLimited => DtlsError::RateLimited,
+            _ => DtlsError::NoRoute,
+        }
+    }
+}
+
+impl From<UnsupportedAddress> for DtlsError {
+    fn from(_: UnsupportedAddress) -> Self {
+        DtlsError::UnsupportedAddress
+    }
+}
+
+impl From<PolicyError> for DtlsError {
+    fn from(value: PolicyError) -> Self {
+        match value {
+            PolicyError::Denied => DtlsError::PermissionDenied,
+            PolicyError::RateLimited => DtlsError::RateLimited,
+        }
+    }
+}
+
+impl From<SendError> for DtlsError {
+    fn from(value: SendError) -> Self {
+        match value {
+            SendError::PacketTooLarge => DtlsError::PacketTooLarge,
+            _ => DtlsError::NoRoute,
+        }
+    }
+}
+
+impl ArielDtlsHost {
+    fn connection(&mut self, handle: &Resource<DtlsSocket>) -> &mut Connection {
+        // Handles only come from `connect` and are removed on drop
+        self.connections[handle.rep() as usize]
+            .as_mut()
+            .expect("Socket used after being dropped")
+    }
+
+    async fn connect(
+        &mut self,
+        udp: &mut ArielUDPHost,
+        remote: Endpoint,
+        identity: &[u8],
+        psk: &[u8],
+    ) -> Result<Resource<DtlsSocket>, DtlsError> {
+        if let Some(pooled) = self.handshaking.take() {
+            udp.release(pooled);
+        }
+        let remote = IpEndpoint::try_from(remote)?;
+        udp.policy.check_remote(&remote)?;
+        let handshaking = self.handshaking.insert(udp.lease()?);
+        let result = Session::connect(
+            &mut handshaking.socket,
+            &mut udp.policy,
+            handshaking.capacity,
+            remote,
+            identity,
+            psk,
+            self.handshake_timeout,
+        )
+        .await;
+        let pooled = self
+            .handshaking
+            .take()
+            .expect("The handshake holds its socket");
+        let session = match result {
+            Ok(session) => session,
+            Err(err) => {
+                udp.release(pooled);
+                return Err(err);
+            }
+        };
+
+        let connection = Connection {
+            pooled,
+            remote,
+            session,
+            received: VecDeque::new(),
+            closed: false,
+        };
+        let index = match self.connections.iter().position(Option::is_none) {
+            Some(index) => {
+                self.connections[index] = Some(connection);
+                index
+            }
+            None => {
+                self.connections.push(Some(connection));
+                self.connections.len() - 1
+            }
+        };
+        Ok(Resource::new_own(index as u32))
+    }
+
+    async fn send(
+        &mut self,
+        udp: &mut ArielUDPHost,
+        self_: Resource<DtlsSocket>,
+        data: Vec<u8>,
+    ) -> Result<(), DtlsError> {
+        let connection = self.connection(&self_);
+        if connection.closed {
+            return Err(DtlsError::Closed);
+        }
+        if data.len() + session::OVERHEAD > connection.pooled.capacity {
+            return Err(DtlsError::PacketTooLarge);
+        }
+        udp.policy.consume(data.len())?;
+        let datagram = connection.session.seal(&data);
+        let remote = connection.remote;
+        connection
+            .pooled
+            .socket
+            .run(|socket| socket.send_to(&datagram, remote))
+            .await
+            .map_err(DtlsError::from)
+    }
+
+    async fn recv(&mut self, self_: Resource<DtlsSocket>) -> Result<Vec<u8>, DtlsError> {
+        let connection = self.connection(&self_);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, connection.pooled.capacity).collect();
+        loop {
+            if let Some(data) = connection.received.pop_front() {
+                return Ok(data);
+            }
+            if connection.closed {
+                return Err(DtlsError::Closed);
+            }
+            // Truncated datagrams can't be authenticated and are dropped like forged ones
+            if let Ok((n, meta)) = connection
+                .pooled
+                .socket
+                .run(|socket| socket.recv_from(&mut buf))
+                .await
+                && meta.endpoint == connection.remote
+            {
+                connection.open(&buf[..n]);
+            }
+        }
+    }
+
+    fn try_recv(&mut self, self_: Resource<DtlsSocket>) -> Result<Option<Vec<u8>>, DtlsError> {
+        let connection = self.connection(&self_);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, connection.pooled.capacity).collect();
+        // Polling without registering interest, the capsule will ask again
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            if let Some(data) = connection.received.pop_front() {
+                return Ok(Some(data));
+            }
+            if connection.closed {
+                return Err(DtlsError::Closed);
+            }
+            match connection.pooled.socket.poll_recv_from(&mut buf, &mut cx) {
+                Poll::Pending => return Ok(None),
+                Poll::Ready(Ok((n, meta))) if meta.endpoint == connection.remote => {
+                    connection.open(&buf[..n]);
+                }
+                Poll::Ready(_) => {}
+            }
+        }
+    }
+
+    fn drop(&mut self, udp: &mut ArielUDPHost, rep: Resource<DtlsSocket>) {
+        // Closing the socket discards anything still queued, so the peer isn't sent a
+        // close_notify and times the session out on its own
+        if let Some(connection) = self
+            .connections
+            .get_mut(rep.rep() as usize)
+            .and_then(Option::take)
+        {
+            udp.release(connection.pooled);
+        }
+    }
+}
+
+impl Connection {
+    fn open(&mut self, datagram: &[u8]) {
+        if self.session.open(datagram, &mut self.received).is_err() {
+            self.closed = true;
+        }
+    }
+}
+
+impl Host for ArielOSHost {}
+
+impl HostDtlsSocket for ArielOSHost {
+    async fn connect(
+        &mut self,
+        remote: Endpoint,
+        identity: Vec<u8>,
+        psk: Resource<Key>,
+    ) -> Result<Resource<DtlsSocket>, DtlsError> {
+        // Cleared when dropped
+        let psk = self
+            .crypto_host
+            .symmetric_secret(&psk)
+            .ok_or(DtlsError::WrongKey)?;
+        self.before_deadline(
+            |host| {
+                host.dtls_host
+                    .connect(&mut host.udp_host, remote, &identity, &psk)
+            },
+            Err(DtlsError::TimedOut),
+        )
+        .await
+    }
+
+    async fn send(&mut self, self_: Resource<DtlsSocket>, data: Vec<u8>) -> Result<(), DtlsError> {
+        self.before_deadline(
+            |host| host.dtls_host.send(&mut host.udp_host, self_, data),
+            Err(DtlsError::TimedOut),
+        )
+        .await
+    }
+
+    async fn recv(&mut self, self_: Resource<DtlsSocket>) -> Result<Vec<u8>, DtlsError> {
+        self.before_deadline(|host| host.dtls_host.recv(self_), Err(DtlsError::TimedOut))
+            .await
+    }
+
+    fn try_recv(&mut self, self_: Resource<DtlsSocket>) -> Result<Option<Vec<u8>>, DtlsError> {
+        self.dtls_host.try_recv(self_)
+    }
+
+    fn drop(&mut self, rep: Resource<DtlsSocket>) -> wasmtime::Result<()> {
+        self.dtls_host.drop(&mut self.udp_host, rep);
+        Ok(())
+    }
+}
+
+impl ArielOSHost {
+    /// Sets how long a DTLS handshake may take before `connect` fails.
+    pub fn set_dtls_handshake_timeout(&mut self, timeout: Duration) {
+        self.dtls_host.handshake_timeout = timeout;
+    }
+}
@@ -0,0 +1,955 @@
+//! Client side of DTLS 1.2 (RFC 6347) with a pre-shared key.
+//!
+//! Only the `TLS_PSK_WITH_AES_128_CCM_8` cipher suite (RFC 6655) is offered, which is the one
+//! CoAP mandates for pre-shared keys (RFC 7252). Handshake messages must not be fragmented,
+//! which holds for the small flights of a PSK handshake. DTLS 1.3 is not supported.
+
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::collections::VecDeque;
+use alloc::vec::Vec;
+
+use aes::Aes128;
+
+use ariel_os_embassy::api::time::{Duration, Instant, Timer};
+use ariel_os_embassy::reexports::embassy_net::IpEndpoint;
+use ariel_os_embassy::reexports::embassy_net::udp::UdpSocket;
+
+use ccm::Ccm;
+use ccm::aead::{Aead, KeyInit, Nonce, Payload};
+use ccm::consts::{U8, U12};
+
+use embassy_futures::select::{Either, select};
+
+use hmac::{Hmac, Mac};
+
+use rand_core::RngCore as _;
+
+use sha2::{Digest as _, Sha256};
+
+use zeroize::Zeroize as _;
+
+use super::{DtlsError, Local, NetPolicy};
+
+type HmacSha256 = Hmac<Sha256>;
+type Cipher = Ccm<Aes128, U8, U12>;
+
+const CHANGE_CIPHER_SPEC: u8 = 20;
+const ALERT: u8 = 21;
+const HANDSHAKE: u8 = 22;
+const APPLICATION_DATA: u8 = 23;
+
+const CLIENT_HELLO: u8 = 1;
+const SERVER_HELLO: u8 = 2;
+const HELLO_VERIFY_REQUEST: u8 = 3;
+const SERVER_KEY_EXCHANGE: u8 = 12;
+const SERVER_HELLO_DONE: u8 = 14;
+const CLIENT_KEY_EXCHANGE: u8 = 16;
+const FINISHED: u8 = 20;
+
+const ALERT_FATAL: u8 = 2;
+const CLOSE_NOTIFY: u8 = 0;
+
+const DTLS_1_2: [u8; 2] = [254, 253];
+const TLS_PSK_WITH_AES_128_CCM_8: [u8; 2] = [0xc0, 0xa8];
+
+const RECORD_HEADER_LEN: usize = 13;
+const HANDSHAKE_HEADER_LEN: usize = 12;
+const EXPLICIT_NONCE_LEN: usize = 8;
+const TAG_LEN: usize = 8;
+const VERIFY_DATA_LEN: usize = 12;
+
+/// Bytes a protected record adds to the data it carries.
+pub(crate) const OVERHEAD: usize = RECORD_HEADER_LEN + EXPLICIT_NONCE_LEN + TAG_LEN;
+
+/// Initial retransmission timeout of a flight (RFC 6347, section 4.2.4.1).
+const INITIAL_RETRANSMIT: Duration = Duration::from_secs(1);
+const MAX_RETRANSMIT: Duration = Duration::from_secs(60);
+
+/// TLS 1.2 PRF with SHA-256 (RFC 5246, section 5).
+fn prf(secret: &[u8], label: &[u8], seeds: &[&[u8]], out: &mut [u8]) {
+    let mac = |parts: &[&[u8]]| {
+        let mut mac =
+            <HmacSha256 as Mac>::new_from_slice(secret).expect("HMAC takes keys of any length");
+        for part in parts {
+            mac.update(part);
+        }
+        mac.finalize().into_bytes()
+    };
+    let mut seed = Vec::from(label);
+    for part in seeds {
+        seed.extend_from_slice(part);
+    }
+    let mut a = mac(&[&seed]);
+    for chunk in out.chunks_mut(32) {
+        let block = mac(&[&a, &seed]);
+        chunk.copy_from_slice(&block[..chunk.len()]);
+        a = mac(&[&a]);
+    }
+}
+
+fn constant_time_eq(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+/// Keys of one direction of the connection.
+struct DirectionKeys {
+    cipher: Cipher,
+    iv: [u8; 4],
+}
+
+impl DirectionKeys {
+    fn nonce(&self, epoch_seq: &[u8; 8]) -> [u8; 12] {
+        let mut nonce = [0; 12];
+        nonce[..4].copy_from_slice(&self.iv);
+        nonce[4..].copy_from_slice(epoch_seq);
+        nonce
+    }
+
+    fn additional_data(epoch_seq: &[u8; 8], content_type: u8, len: usize) -> [u8; 13] {
+        let mut aad = [0; 13];
+        aad[..8].copy_from_slice(epoch_seq);
+        aad[8] = content_type;
+        aad[9..11].copy_from_slice(&DTLS_1_2);
+        aad[11..].copy_from_slice(&(len as u16).to_be_bytes());
+        aad
+    }
+
+    fn seal(&self, content_type: u8, epoch: u16, seq: u64, plaintext: &[u8]) -> Vec<u8> {
+        let epoch_seq = ((u64::from(epoch) << 48) | seq).to_be_bytes();
+        let aad = Self::additional_data(&epoch_seq, content_type, plaintext.len());
+        let ciphertext = self
+            .cipher
+            .encrypt(
+                Nonce::<Cipher>::from_slice(&self.nonce(&epoch_seq)),
+                Payload {
+                    msg: plaintext,
+                    aad: &aad,
+                },
+            )
+            .expect("Records are far below the CCM length limit");
+        let mut fragment = Vec::with_capacity(EXPLICIT_NONCE_LEN + ciphertext.len());
+        fragment.extend_from_slice(&epoch_seq);
+        fragment.extend_from_slice(&ciphertext);
+        fragment
+    }
+
+    fn open(&self, record: &Record<'_>) -> Option<Vec<u8>> {
+        let (explicit_nonce, ciphertext) = record.fragment.split_at_checked(EXPLICIT_NONCE_LEN)?;
+        let len = ciphertext.len().checked_sub(TAG_LEN)?;
+        let epoch_seq = ((u64::from(record.epoch) << 48) | record.seq).to_be_bytes();
+        let aad = Self::additional_data(&epoch_seq, record.content_type, len);
+        let explicit_nonce: [u8; 8] = explicit_nonce.try_into().ok()?;
+        self.cipher
+            .decrypt(
+                Nonce::<Cipher>::from_slice(&self.nonce(&explicit_nonce)),
+                Payload {
+                    msg: ciphertext,
+                    aad: &aad,
+                },
+            )
+            .ok()
+    }
+}
+
+impl Drop for DirectionKeys {
+    fn drop(&mut self) {
+        // The cipher clears its key schedule itself
+        self.iv.zeroize();
+    }
+}
+
+struct Keys {
+    write: DirectionKeys,
+    read: DirectionKeys,
+}
+
+impl Keys {
+    /// Derives the keys of the client from the master secret (RFC 5246, section 6.3).
+    fn derive(master_secret: &[u8; 48], client_random: &[u8], server_random: &[u8]) -> Self {
+        let mut block = [0; 40];
+        prf(
+            master_secret,
+            b"key expansion",
+            &[server_random, client_random],
+            &mut block,
+        );
+        let direction = |key: &[u8], iv: &[u8]| DirectionKeys {
+            cipher: Cipher::new_from_slice(key).expect("The key block holds AES-128 keys"),
+            iv: iv.try_into().expect("The key block holds 4-byte IVs"),
+        };
+        let keys = Self {
+            write: direction(&block[..16], &block[32..36]),
+            read: direction(&block[16..32], &block[36..40]),
+        };
+        block.fill(0);
+        keys
+    }
+}
+
+/// Sliding window discarding replayed records (RFC 6347, section 4.1.2.6).
+#[derive(Default)]
+struct ReplayWindow {
+    highest: Option<u64>,
+    seen: u64,
+}
+
+impl ReplayWindow {
+    fn is_fresh(&self, seq: u64) -> bool {
+        match self.highest {
+            Some(highest) if seq <= highest => {
+                let age = highest - seq;
+                age < 64 && self.seen & (1 << age) == 0
+            }
+            _ => true,
+        }
+    }
+
+    fn mark(&mut self, seq: u64) {
+        match self.highest {
+            Some(highest) if seq <= highest => self.seen |= 1 << (highest - seq),
+            Some(highest) => {
+                let shifted = u32::try_from(seq - highest)
+                    .ok()
+                    .and_then(|shift| self.seen.checked_shl(shift));
+                self.seen = shifted.unwrap_or(0) | 1;
+                self.highest = Some(seq);
+            }
+            None => {
+                self.seen = 1;
+                self.highest = Some(seq);
+            }
+        }
+    }
+}
+
+struct Record<'a> {
+    content_type: u8,
+    epoch: u16,
+    seq: u64,
+    fragment: &'a [u8],
+}
+
+/// Splits a datagram into its records, ignoring anything after a malformed one.
+fn parse_records(mut data: &[u8]) -> Vec<Record<'_>> {
+    let mut records = Vec::new();
+    while data.len() >= RECORD_HEADER_LEN {
+        let len = usize::from(u16::from_be_bytes([data[11], data[12]]));
+        let Some(fragment) = data.get(RECORD_HEADER_LEN..RECORD_HEADER_LEN + len) else {
+            break;
+        };
+        // Only the major version is checked, some servers answer the first ClientHello with the
+        // minor version of DTLS 1.0
+        if data[1] != DTLS_1_2[0] {
+            break;
+        }
+        let mut seq = [0; 8];
+        seq[2..].copy_from_slice(&data[5..11]);
+        records.push(Record {
+            content_type: data[0],
+            epoch: u16::from_be_bytes([data[3], data[4]]),
+            seq: u64::from_be_bytes(seq),
+            fragment,
+        });
+        data = &data[RECORD_HEADER_LEN + len..];
+    }
+    records
+}
+
+fn write_record(out: &mut Vec<u8>, content_type: u8, epoch: u16, seq: u64, fragment: &[u8]) {
+    out.push(content_type);
+    out.extend_from_slice(&DTLS_1_2);
+    out.extend_from_slice(&epoch.to_be_bytes());
+    out.extend_from_slice(&seq.to_be_bytes()[2..]);
+    out.extend_from_slice(&(fragment.len() as u16).to_be_bytes());
+    out.extend_from_slice(fragment);
+}
+
+struct HandshakeMessage<'a> {
+    msg_type: u8,
+    message_seq: u16,
+    body: &'a [u8],
+    /// The whole message, as it is hashed into the transcript.
+    raw: &'a [u8],
+}
+
+fn read_u24(bytes: &[u8]) -> usize {
+    (usize::from(bytes[0]) << 16) | (usize::from(bytes[1]) << 8) | usize::from(bytes[2])
+}
+
+fn parse_handshake(mut data: &[u8]) -> Result<Vec<HandshakeMessage<'_>>, DtlsError> {
+    let mut messages = Vec::new();
+    while !data.is_empty() {
+        if data.len() < HANDSHAKE_HEADER_LEN {
+            return Err(DtlsError::HandshakeFailed);
+        }
+        let len = read_u24(&data[1..4]);
+        if read_u24(&data[6..9]) != 0 || read_u24(&data[9..12]) != len {
+            // Fragmented messages are not supported
+            return Err(DtlsError::HandshakeFailed);
+        }
+        let raw = data
+            .get(..HANDSHAKE_HEADER_LEN + len)
+            .ok_or(DtlsError::HandshakeFailed)?;
+        messages.push(HandshakeMessage {
+            msg_type: data[0],
+            message_seq: u16::from_be_bytes([data[4], data[5]]),
+            body: &raw[HANDSHAKE_HEADER_LEN..],
+            raw,
+        });
+        data = &data[raw.len()..];
+    }
+    Ok(messages)
+}
+
+fn handshake_message(msg_type: u8, message_seq: u16, body: &[u8]) -> Vec<u8> {
+    let len = (body.len() as u32).to_be_bytes();
+    let mut message = Vec::with_capacity(HANDSHAKE_HEADER_LEN + body.len());
+    message.push(msg_type);
+    message.extend_from_slice(&len[1..]);
+    message.extend_from_slice(&message_seq.to_be_bytes());
+    message.extend_from_slice(&[0; 3]);
+    message.extend_from_slice(&len[1..]);
+    message.extend_from_slice(body);
+    message
+}
+
+fn client_hello_body(random: &[u8; 32], cookie: &[u8]) -> Vec<u8> {
+    let mut body = Vec::with_capacity(41 + cookie.len());
+    body.extend_from_slice(&DTLS_1_2);
+    body.extend_from_slice(random);
+    // No session to resume
+    body.push(0);
+    body.push(cookie.len() as u8);
+    body.extend_from_slice(cookie);
+    body.extend_from_slice(&2u16.to_be_bytes());
+    body.extend_from_slice(&TLS_PSK_WITH_AES_128_CCM_8);
+    // Only the null compression method
+    body.extend_from_slice(&[1, 0]);
+    body
+}
+
+/// Returns the random of the server if the ServerHello selects what was offered.
+fn parse_server_hello(body: &[u8]) -> Option<[u8; 32]> {
+    let random: [u8; 32] = body.get(2..34)?.try_into().ok()?;
+    let session_id_len = usize::from(*body.get(34)?);
+    let rest = body.get(35 + session_id_len..)?;
+    // The cipher suite has to be the offered one, with the null compression method
+    (body[..2] == DTLS_1_2 && rest.get(..2)? == TLS_PSK_WITH_AES_128_CCM_8 && rest.get(2)? == &0)
+        .then_some(random)
+}
+
+/// Records of a flight, kept as plaintext so retransmissions get fresh sequence numbers.
+struct FlightRecord {
+    content_type: u8,
+    epoch: u16,
+    payload: Vec<u8>,
+}
+
+enum State {
+    /// Waiting for a HelloVerifyRequest or the ServerHello.
+    Hello,
+    /// Waiting for the rest of the flight of the server, up to ServerHelloDone.
+    ServerFlight,
+    /// Waiting for the ChangeCipherSpec and Finished of the server.
+    Finished,
+}
+
+/// What a datagram received during the handshake led to.
+enum Progress {
+    /// The flight of the server is not complete yet.
+    Waiting,
+    /// The next flight of the client is ready to be sent.
+    NextFlight,
+    Established(Box<Session>),
+}
+
+/// State of a handshake in progress.
+struct Client<'a> {
+    identity: &'a [u8],
+    psk: &'a [u8],
+    state: State,
+    client_random: [u8; 32],
+    server_random: [u8; 32],
+    master_secret: [u8; 48],
+    transcript: Sha256,
+    /// Next `message_seq` to send and to receive.
+    send_seq: u16,
+    receive_seq: u16,
+    server_changed_cipher: bool,
+    /// Next record sequence number of epochs 0 and 1.
+    write_seq: [u64; 2],
+    keys: Option<Keys>,
+    flight: Vec<FlightRecord>,
+}
+
+impl<'a> Client<'a> {
+    fn new(identity: &'a [u8], psk: &'a [u8]) -> Self {
+        let mut client_random = [0; 32];
+        ariel_os_random::crypto_rng().fill_bytes(&mut client_random);
+        Self::with_random(identity, psk, client_random)
+    }
+
+    fn with_random(identity: &'a [u8], psk: &'a [u8], client_random: [u8; 32]) -> Self {
+        let mut client = Self {
+            identity,
+            psk,
+            state: State::Hello,
+            client_random,
+            server_random: [0; 32],
+            master_secret: [0; 48],
+            transcript: Sha256::new(),
+            send_seq: 0,
+            receive_seq: 0,
+            server_changed_cipher: false,
+            write_seq: [0; 2],
+            keys: None,
+            flight: Vec::new(),
+        };
+        let hello = client.message(CLIENT_HELLO, &client_hello_body(&client_random, &[]), 0);
+        client.flight.push(hello);
+        client
+    }
+
+    /// Builds the next handshake message of the client and adds it to the transcript.
+    fn message(&mut self, msg_type: u8, body: &[u8], epoch: u16) -> FlightRecord {
+        let payload = handshake_message(msg_type, self.send_seq, body);
+        self.send_seq += 1;
+        self.transcript.update(&payload);
+        FlightRecord {
+            content_type: HANDSHAKE,
+            epoch,
+            payload,
+        }
+    }
+
+    fn verify_data(&self, label: &[u8]) -> [u8; VERIFY_DATA_LEN] {
+        let mut verify_data = [0; VERIFY_DATA_LEN];
+        prf(
+            &self.master_secret,
+            label,
+            &[&self.transcript.clone().finalize()],
+            &mut verify_data,
+        );
+        verify_data
+    }
+
+    /// Encodes the current flight, with fresh record sequence numbers.
+    fn datagram(&mut self) -> Vec<u8> {
+        let mut datagram = Vec::new();
+        for record in &self.flight {
+            let epoch = record.epoch;
+            let seq = self.write_seq[usize::from(epoch)];
+            self.write_seq[usize::from(epoch)] += 1;
+            match (epoch, &self.keys) {
+                (0, _) => write_record(&mut datagram, record.content_type, 0, seq, &record.payload),
+                (_, Some(keys)) => {
+                    let fragment =
+                        keys.write
+                            .seal(record.content_type, epoch, seq, &record.payload);
+                    write_record(&mut datagram, record.content_type, epoch, seq, &fragment);
+                }
+                (_, None) => unreachable!("Epoch 1 starts once the keys are derived"),
+            }
+        }
+        datagram
+    }
+
+    fn receive(&mut self, datagram: &[u8]) -> Result<Progress, DtlsError> {
+        let mut progress = Progress::Waiting;
+        for record in parse_records(datagram) {
+            let plaintext;
+            let fragment = match (record.epoch, &self.keys) {
+                (0, _) => record.fragment,
+                (1, Some(keys)) => match keys.read.open(&record) {
+                    Some(decrypted) => {
+                        plaintext = decrypted;
+                        &plaintext
+                    }
+                    None => continue,
+                },
+                _ => continue,
+            };
+
+            match record.content_type {
+                ALERT if fragment.first() == Some(&ALERT_FATAL) => {
+                    return Err(DtlsError::HandshakeFailed);
+                }
+                CHANGE_CIPHER_SPEC
+                    if record.epoch == 0 && matches!(self.state, State::Finished) =>
+                {
+                    self.server_changed_cipher = true;
+                }
+                HANDSHAKE => {
+                    for message in parse_handshake(fragment)? {
+                        // Retransmitted and early messages are dropped, the server resends the
+                        // latter once the client retransmits its flight
+                        if message.message_seq != self.receive_seq {
+                            continue;
+                        }
+                        // Only the Finished of the server is protected
+                        if (record.epoch == 1) != (message.msg_type == FINISHED) {
+                            return Err(DtlsError::HandshakeFailed);
+                        }
+                        self.receive_seq += 1;
+                        match self.handle(&message)? {
+                            Progress::Waiting => {}
+                            Progress::NextFlight => progress = Progress::NextFlight,
+                            established => return Ok(established),
+                        }
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(progress)
+    }
+
+    fn handle(&mut self, message: &HandshakeMessage<'_>) -> Result<Progress, DtlsError> {
+        match (&self.state, message.msg_type) {
+            (State::Hello, HELLO_VERIFY_REQUEST) => {
+                let cookie = message
+                    .body
+                    .get(2)
+                    .and_then(|&len| message.body.get(3..3 + usize::from(len)))
+                    .ok_or(DtlsError::HandshakeFailed)?;
+                // The exchange up to here is not part of the transcript
+                self.transcript = Sha256::new();
+                let body = client_hello_body(&self.client_random, cookie);
+                self.flight = alloc::vec![self.message(CLIENT_HELLO, &body, 0)];
+                Ok(Progress::NextFlight)
+            }
+            (State::Hello, SERVER_HELLO) => {
+                self.server_random =
+                    parse_server_hello(message.body).ok_or(DtlsError::HandshakeFailed)?;
+                self.transcript.update(message.raw);
+                self.state = State::ServerFlight;
+                Ok(Progress::Waiting)
+            }
+            (State::ServerFlight, SERVER_KEY_EXCHANGE) => {
+                // Only carries an identity hint, which isn't used
+                self.transcript.update(message.raw);
+                Ok(Progress::Waiting)
+            }
+            (State::ServerFlight, SERVER_HELLO_DONE) => {
+                self.transcript.update(message.raw);
+                self.key_exchange();
+                self.state = State::Finished;
+                Ok(Progress::NextFlight)
+            }
+            (State::Finished, FINISHED) if self.server_changed_cipher => {
+                if !constant_time_eq(&self.verify_data(b"server finished"), message.body) {
+                    return Err(DtlsError::HandshakeFailed);
+                }
+                Ok(Progress::Established(Box::new(Session {
+                    keys: self
+                        .keys
+                        .take()
+                        .expect("Keys are derived before the Finished"),
+                    write_seq: self.write_seq[1],
+                    replay: ReplayWindow::default(),
+                })))
+            }
+            // Anything else, e.g. a certificate, means the server doesn't do plain PSK
+            _ => Err(DtlsError::HandshakeFailed),
+        }
+    }
+
+    /// Derives the keys and prepares the ClientKeyExchange, ChangeCipherSpec and Finished.
+    fn key_exchange(&mut self) {
+        // Premaster secret of a plain PSK (RFC 4279, section 2)
+        let psk_len = (self.psk.len() as u16).to_be_bytes();
+        let mut premaster = Vec::with_capacity(4 + 2 * self.psk.len());
+        premaster.extend_from_slice(&psk_len);
+        premaster.resize(2 + self.psk.len(), 0);
+        premaster.extend_from_slice(&psk_len);
+        premaster.extend_from_slice(self.psk);
+        prf(
+            &premaster,
+            b"master secret",
+            &[&self.client_random, &self.server_random],
+            &mut self.master_secret,
+        );
+        premaster.fill(0);
+        self.keys = Some(Keys::derive(
+            &self.master_secret,
+            &self.client_random,
+            &self.server_random,
+        ));
+
+        let mut body = Vec::with_capacity(2 + self.identity.len());
+        body.extend_from_slice(&(self.identity.len() as u16).to_be_bytes());
+        body.extend_from_slice(self.identity);
+        let key_exchange = self.message(CLIENT_KEY_EXCHANGE, &body, 0);
+        let verify_data = self.verify_data(b"client finished");
+        let finished = self.message(FINISHED, &verify_data, 1);
+        self.flight = alloc::vec![
+            key_exchange,
+            FlightRecord {
+                content_type: CHANGE_CIPHER_SPEC,
+                epoch: 0,
+                payload: alloc::vec![1],
+            },
+            finished,
+        ];
+    }
+}
+
+impl Drop for Client<'_> {
+    fn drop(&mut self) {
+        self.master_secret.zeroize();
+    }
+}
+
+/// An established DTLS session, its keys are cleared when it is dropped.
+pub(crate) struct Session {
+    keys: Keys,
+    write_seq: u64,
+    replay: ReplayWindow,
+}
+
+impl Session {
+    /// Performs the handshake with `remote` through `socket`, which must be bound.
+    ///
+    /// Every datagram sent, retransmissions included, is accounted for in `policy`.
+    pub(crate) async fn connect(
+        socket: &mut Local<UdpSocket<'static>>,
+        policy: &mut NetPolicy,
+        capacity: usize,
+        remote: IpEndpoint,
+        identity: &[u8],
+        psk: &[u8],
+        timeout: Duration,
+    ) -> Result<Self, DtlsError> {
+        if identity.len() > usize::from(u16::MAX) || psk.len() > usize::from(u16::MAX) {
+            return Err(DtlsError::WrongKey);
+        }
+        let deadline = Instant::now() + timeout;
+        let mut client = Client::new(identity, psk);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, capacity).collect();
+
+        loop {
+            let datagram = client.datagram();
+            policy.consume(datagram.len())?;
+            socket
+                .run(|socket| socket.send_to(&datagram, remote))
+                .await
+                .map_err(|_| DtlsError::NoRoute)?;
+            let mut retransmit = INITIAL_RETRANSMIT;
+            let mut retransmit_at = Instant::now() + retransmit;
+            // Runs until the next flight is ready
+            loop {
+                let timer = Timer::at(retransmit_at.min(deadline));
+                match select(socket.run(|socket| socket.recv_from(&mut buf)), timer).await {
+                    Either::First(Ok((n, meta))) if meta.endpoint == remote => {
+                        match client.receive(&buf[..n])? {
+                            Progress::Waiting => {}
+                            Progress::NextFlight => break,
+                            Progress::Established(session) => return Ok(*session),
+                        }
+                    }
+                    Either::First(_) => {}
+                    Either::Second(()) if Instant::now() >= deadline => {
+                        return Err(DtlsError::TimedOut);
+                    }
+                    Either::Second(()) => {
+                        let datagram = client.datagram();
+                        policy.consume(datagram.len())?;
+                        socket
+                            .run(|socket| socket.send_to(&datagram, remote))
+                            .await
+                            .map_err(|_| DtlsError::NoRoute)?;
+                        retransmit = (retransmit * 2).min(MAX_RETRANSMIT);
+                        retransmit_at = Instant::now() + retransmit;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Protects `data` into a datagram.
+    pub(crate) fn seal(&mut self, data: &[u8]) -> Vec<u8> {
+        let seq = self.write_seq;
+        self.write_seq += 1;
+        let fragment = self.keys.write.seal(APPLICATION_DATA, 1, seq, data);
+        let mut datagram = Vec::with_capacity(RECORD_HEADER_LEN + fragment.len());
+        write_record(&mut datagram, APPLICATION_DATA, 1, seq, &fragment);
+        datagram
+    }
+
+    /// Appends the application data of a received datagram to `received`.
+    ///
+    /// Records that aren't authentic or were already received are dropped. Fails once the peer
+    /// closed the session.
+    pub(crate) fn open(
+        &mut self,
+        datagram: &[u8],
+        received: &mut VecDeque<Vec<u8>>,
+    ) -> Result<(), DtlsError> {
+        for record in parse_records(datagram) {
+            if record.epoch != 1 || !self.replay.is_fresh(record.seq) {
+                continue;
+            }
+            let Some(plaintext) = self.keys.read.open(&record) else {
+                continue;
+            };
+            self.replay.mark(record.seq);
+            match record.content_type {
+                APPLICATION_DATA => received.push_back(plaintext),
+                ALERT
+                    if plaintext.first() == Some(&ALERT_FATAL)
+                        || plaintext.get(1) == Some(&CLOSE_NOTIFY) =>
+                {
+                    return Err(DtlsError::Closed);
+                }
+                // Retransmissions of the last flight of the server
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unhex(hex: &str) -> Vec<u8> {
+        (0..hex.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&hex[i..i + 2], 16).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn prf_sha256() {
+        // Test vector of the TLS 1.2 PRF with SHA-256
+        let mut out = [0; 100];
+        prf(
+            &unhex("9bbe436ba940f017b17652849a71db35"),
+            b"test label",
+            &[&unhex("a0ba9f936cda311827a6f796ffd5198c")],
+            &mut out,
+        );
+        assert_eq!(
+            out[..],
+            unhex(concat!(
+                "e3f229ba727be17b8d122620557cd453c2aab21d07c3d495329b52d4e61edb5a6b301791e90d35c9",
+                "c9a46b4e14baf9af0fa022f7077def17abfd3797c0564bab4fbc91666e9def9b97fce34f796789ba",
+                "a48082d122ee42c5a72e5a5110fff70187347b66",
+            ))
+        );
+    }
+
+    fn direction_keys() -> DirectionKeys {
+        DirectionKeys {
+            cipher: Cipher::new_from_slice(&[7; 16]).unwrap(),
+            iv: [1, 2, 3, 4],
+        }
+    }
+
+    fn sealed_record(seq: u64, data: &[u8]) -> Vec<u8> {
+        let fragment = direction_keys().seal(APPLICATION_DATA, 1, seq, data);
+        let mut datagram = Vec::new();
+        write_record(&mut datagram, APPLICATION_DATA, 1, seq, &fragment);
+        datagram
+    }
+
+    #[test]
+    fn seals_and_opens() {
+        let datagram = sealed_record(5, b"data");
+        assert_eq!(datagram.len(), OVERHEAD + 4);
+        let records = parse_records(&datagram);
+        assert_eq!(direction_keys().open(&records[0]).unwrap(), b"data");
+    }
+
+    #[test]
+    fn rejects_tampered_records() {
+        let keys = direction_keys();
+        let datagram = sealed_record(5, b"data");
+
+        // Content type and sequence number of the header, explicit nonce, ciphertext and tag
+        for index in [
+            0,
+            RECORD_HEADER_LEN - 3,
+            RECORD_HEADER_LEN,
+            RECORD_HEADER_LEN + EXPLICIT_NONCE_LEN,
+            datagram.len() - 1,
+        ] {
+            let mut tampered = datagram.clone();
+            tampered[index] ^= 1;
+            assert!(keys.open(&parse_records(&tampered)[0]).is_none());
+        }
+
+        // Shorter than the explicit nonce and the tag
+        let mut short = Vec::new();
+        write_record(
+            &mut short,
+            APPLICATION_DATA,
+            1,
+            5,
+            &[0; EXPLICIT_NONCE_LEN + TAG_LEN - 1],
+        );
+        assert!(keys.open(&parse_records(&short)[0]).is_none());
+    }
+
+    #[test]
+    fn replay_window() {
+        let mut window = ReplayWindow::default();
+        assert!(window.is_fresh(100));
+        window.mark(100);
+        assert!(!window.is_fresh(100));
+
+        // Age 63 is the oldest the window tracks, age 64 is already too old
+        assert!(window.is_fresh(37));
+        assert!(!window.is_fresh(36));
+        window.mark(37);
+        assert!(!window.is_fresh(37));
+        assert!(window.is_fresh(38));
+
+        // Moving forward keeps what was seen within the window
+        window.mark(101);
+        assert!(!window.is_fresh(100));
+        assert!(window.is_fresh(99));
+        assert!(!window.is_fresh(37));
+
+        // Jumps past the window forget everything before them
+        window.mark(1101);
+        assert!(!window.is_fresh(101));
+        assert!(window.is_fresh(1101 - 63));
+        window.mark(1 << 47);
+        assert!(!window.is_fresh(1 << 47));
+        assert!(window.is_fresh((1 << 47) - 1));
+        assert!(!window.is_fresh(1101));
+    }
+
+    #[test]
+    fn parses_truncated_records() {
+        let datagram = sealed_record(5, b"data");
+        assert!(parse_records(&datagram[..RECORD_HEADER_LEN - 1]).is_empty());
+        // Length past the end of the datagram
+        assert!(parse_records(&datagram[..datagram.len() - 1]).is_empty());
+
+        // A record followed by a truncated one
+        let mut two = datagram.clone();
+        two.extend_from_slice(&datagram[..RECORD_HEADER_LEN + 2]);
+        assert_eq!(parse_records(&two).len(), 1);
+    }
+
+    #[test]
+    fn parses_truncated_handshakes() {
+        let message = handshake_message(FINISHED, 3, &[9; VERIFY_DATA_LEN]);
+        let parsed = parse_handshake(&message).unwrap();
+        assert_eq!(
+            (parsed[0].msg_type, parsed[0].message_seq, parsed[0].body),
+            (FINISHED, 3, &[9; VERIFY_DATA_LEN][..])
+        );
+
+        assert!(parse_handshake(&message[..HANDSHAKE_HEADER_LEN - 1]).is_err());
+        assert!(parse_handshake(&message[..message.len() - 1]).is_err());
+        // Fragment of a larger message
+        let mut fragment = message.clone();
+        fragment[HANDSHAKE_HEADER_LEN - 1] -= 1;
+        assert!(parse_handshake(&fragment).is_err());
+    }
+
+    // Recorded against `openssl s_server -dtls1_2 -listen -nocert -psk_hint hint
+    // -psk 11111111111111111111111111111111 -cipher 'PSK-AES128-CCM8:@SECLEVEL=0'`, with the
+    // identity `ariel` and 32 times 0x42 as client random.
+
+    /// ClientHello without a cookie.
+    const CLIENT_HELLO_1: &str = concat!(
+        "16fefd000000000000000000360100002a000000000000002afefd42424242424242424242424242",
+        "4242424242424242424242424242424242424200000002c0a80100",
+    );
+
+    /// HelloVerifyRequest, with the minor version of DTLS 1.0.
+    const HELLO_VERIFY_REQUEST: &str = concat!(
+        "16feff00000000000000000023030000170000000000000017feff146a4eacc4910ebb45ba0cd8d2",
+        "a8a622cf90622b21",
+    );
+
+    /// ClientHello with the cookie.
+    const CLIENT_HELLO_2: &str = concat!(
+        "16fefd0000000000000001004a0100003e000100000000003efefd42424242424242424242424242",
+        "4242424242424242424242424242424242424200146a4eacc4910ebb45ba0cd8d2a8a622cf90622b",
+        "210002c0a80100",
+    );
+
+    /// ServerHello, ServerKeyExchange with the hint `hint` and ServerHelloDone.
+    const SERVER_FLIGHT: &str = concat!(
+        "16fefd00000000000000010052020000460001000000000046fefdbb225fb775713016c99b5afa6e",
+        "ecf7a251f625b5d2d1bfa15b162c00e9ecb081202d94948526b97f5c71a4a225cc1f8877538c1ad1",
+        "32311eacfc18b7168cde7f7ac0a80016fefd000000000000000200120c0000060002000000000006",
+        "000468696e7416fefd0000000000000003000c0e0000000003000000000000",
+    );
+
+    /// ClientKeyExchange with the identity `ariel`, ChangeCipherSpec and Finished.
+    const CLIENT_FLIGHT: &str = concat!(
+        "16fefd000000000000000200131000000700020000000000070005617269656c14fefd0000000000",
+        "00000300010116fefd0001000000000000002800010000000000007964242e8791dd0f4ebdf7c76c",
+        "0a2ebc2b38d8f42183c39d3c0e8c75d2dec09e",
+    );
+
+    /// ChangeCipherSpec and Finished of the server.
+    const SERVER_FINISHED: &str = concat!(
+        "14fefd000000000000000400010116fefd0001000000000000002800010000000000003f446d08ab",
+        "d7f169c873067e27424f61d2c61e65f73173c8deafa4c044ed375c",
+    );
+
+    /// `hello\n` from the client.
+    const CLIENT_DATA: &str =
+        "17fefd00010000000000010016000100000000000191a7b708b0b39fa7a9209aeaf97c";
+
+    /// `world\n` from the server.
+    const SERVER_DATA: &str =
+        "17fefd0001000000000001001600010000000000012ce72fdbff24ad52057ca12025f5";
+
+    fn client(psk: &[u8]) -> Client<'_> {
+        Client::with_random(b"ariel", psk, [0x42; 32])
+    }
+
+    #[test]
+    fn handshake_with_openssl() {
+        let psk = [0x11; 16];
+        let mut client = client(&psk);
+
+        assert_eq!(client.datagram(), unhex(CLIENT_HELLO_1));
+        assert!(matches!(
+            client.receive(&unhex(HELLO_VERIFY_REQUEST)),
+            Ok(Progress::NextFlight)
+        ));
+        assert_eq!(client.datagram(), unhex(CLIENT_HELLO_2));
+        assert!(matches!(
+            client.receive(&unhex(SERVER_FLIGHT)),
+            Ok(Progress::NextFlight)
+        ));
+        assert_eq!(client.datagram(), unhex(CLIENT_FLIGHT));
+        let Ok(Progress::Established(mut session)) = client.receive(&unhex(SERVER_FINISHED)) else {
+            panic!("The handshake should be complete");
+        };
+
+        assert_eq!(session.seal(b"hello\n"), unhex(CLIENT_DATA));
+        let mut received = VecDeque::new();
+        session.open(&unhex(SERVER_DATA), &mut received).unwrap();
+        assert_eq!(received, [b"world\n"]);
+
+        // Replayed records are dropped
+        session.open(&unhex(SERVER_DATA), &mut received).unwrap();
+        assert_eq!(received.len(), 1);
+    }
+
+    #[test]
+    fn ignores_the_finished_of_another_key() {
+        let psk = [0x22; 16];
+        let mut client = client(&psk);
+        client.receive(&unhex(HELLO_VERIFY_REQUEST)).unwrap();
+        client.receive(&unhex(SERVER_FLIGHT)).unwrap();
+        assert_ne!(client.datagram(), unhex(CLIENT_FLIGHT));
+        // Can't be decrypted, and is thus dropped like any forged record
+        assert!(matches!(
+            client.receive(&unhex(SERVER_FINISHED)),
+            Ok(Progress::Waiting)
+        ));
+    }
+}
@@ -40,7 +40,8 @@ impl Host for ArielOSHost {
     }
 
     async fn wait_for_button_low(&mut self) -> Result<(), ()> {
-        self.gpio_host.wait_for_button_low().await
+        self.before_deadline(|host| host.gpio_host.wait_for_button_low(), Err(()))
+            .await
     }
 }
 
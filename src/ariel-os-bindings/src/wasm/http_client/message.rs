@@ -0,0 +1,430 @@
+//! URLs, request heads and response heads of HTTP/1.1 (RFC 9112).
+
+extern crate alloc;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::fmt::Write as _;
+
+use ariel_os_embassy::reexports::embassy_net::IpAddress;
+
+use super::{Header, HttpError, Method};
+
+/// Largest status line and header section accepted from the server.
+pub(crate) const MAX_HEAD_LEN: usize = 2048;
+
+/// Headers the host sets itself.
+const RESERVED_HEADERS: [&str; 4] = ["host", "content-length", "connection", "transfer-encoding"];
+
+pub(crate) struct Url<'a> {
+    pub(crate) host: &'a str,
+    pub(crate) port: u16,
+    /// Host and port as given, for the `Host` header.
+    pub(crate) authority: &'a str,
+    pub(crate) target: &'a str,
+}
+
+pub(crate) fn parse_url(url: &str) -> Result<Url<'_>, HttpError> {
+    let Some((scheme, rest)) = url.split_once("://") else {
+        return Err(HttpError::InvalidUrl);
+    };
+    if !scheme.eq_ignore_ascii_case("http") {
+        return Err(HttpError::UnsupportedScheme);
+    }
+    // The fragment is never sent
+    let rest = rest.split('#').next().unwrap_or_default();
+    let (authority, target) = match rest.find(['/', '?']) {
+        Some(index) => rest.split_at(index),
+        None => (rest, ""),
+    };
+    // User information is not supported, and the request head can't contain line breaks
+    let invalid = |c: char| c.is_ascii_whitespace() || c.is_ascii_control();
+    if authority.contains('@') || authority.contains(invalid) || target.contains(invalid) {
+        return Err(HttpError::InvalidUrl);
+    }
+
+    let (host, port) = match authority.strip_prefix('[') {
+        // IPv6 literal
+        Some(bracketed) => {
+            let (host, after) = bracketed.split_once(']').ok_or(HttpError::InvalidUrl)?;
+            let port = match after {
+                "" => None,
+                _ => Some(after.strip_prefix(':').ok_or(HttpError::InvalidUrl)?),
+            };
+            (host, port)
+        }
+        None => match authority.split_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port {
+        // Parsing alone would accept a sign
+        Some(port) if port.bytes().all(|b| b.is_ascii_digit()) => {
+            port.parse().map_err(|_| HttpError::InvalidUrl)?
+        }
+        Some(_) => return Err(HttpError::InvalidUrl),
+        None => 80,
+    };
+    if host.is_empty() {
+        return Err(HttpError::InvalidUrl);
+    }
+    Ok(Url {
+        host,
+        port,
+        authority,
+        target,
+    })
+}
+
+/// Parses `host` if it is an IP address rather than a name.
+pub(crate) fn literal_address(host: &str) -> Result<Option<IpAddress>, HttpError> {
+    if let Ok(addr) = host.parse::<core::net::Ipv4Addr>() {
+        return Ok(Some(IpAddress::Ipv4(addr)));
+    }
+    if host.contains(':') {
+        #[cfg(feature = "ipv6")]
+        return host
+            .parse::<core::net::Ipv6Addr>()
+            .map(|addr| Some(IpAddress::Ipv6(addr)))
+            .map_err(|_| HttpError::InvalidUrl);
+        #[cfg(not(feature = "ipv6"))]
+        return Err(HttpError::UnsupportedAddress);
+    }
+    Ok(None)
+}
+
+fn is_token(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|b| b.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&b))
+}
+
+fn method_name(method: Method) -> &'static str {
+    match method {
+        Method::Get => "GET",
+        Method::Head => "HEAD",
+        Method::Post => "POST",
+        Method::Put => "PUT",
+        Method::Delete => "DELETE",
+        Method::Patch => "PATCH",
+        Method::Options => "OPTIONS",
+    }
+}
+
+/// Builds the request line and headers, asking the server to close the connection after the
+/// response.
+pub(crate) fn request_head(
+    method: Method,
+    url: &Url<'_>,
+    headers: &[Header],
+    body_len: Option<usize>,
+) -> Result<Vec<u8>, HttpError> {
+    // The target of a URL without a path is the root
+    let slash = if url.target.starts_with('/') { "" } else { "/" };
+    let mut head = format!(
+        "{} {slash}{} HTTP/1.1\r\nHost: {}\r\nConnection: close\r\n",
+        method_name(method),
+        url.target,
+        url.authority,
+    );
+    if let Some(len) = body_len {
+        let _ = write!(head, "Content-Length: {len}\r\n");
+    }
+    for Header { name, value } in headers {
+        if !is_token(name)
+            || value.contains(['\r', '\n', '\0'])
+            || RESERVED_HEADERS
+                .iter()
+                .any(|reserved| name.eq_ignore_ascii_case(reserved))
+        {
+            return Err(HttpError::InvalidHeader);
+        }
+        let _ = write!(head, "{name}: {}\r\n", value.trim());
+    }
+    head.push_str("\r\n");
+    Ok(head.into_bytes())
+}
+
+pub(crate) struct ResponseHead {
+    pub(crate) status: u16,
+    pub(crate) headers: Vec<Header>,
+    /// Length of the status line and headers, including the empty line ending them.
+    pub(crate) len: usize,
+}
+
+/// Parses the response head at the start of `buf`, or returns `None` if it is not complete yet.
+pub(crate) fn parse_response_head(buf: &[u8]) -> Result<Option<ResponseHead>, HttpError> {
+    let Some(end) = buf.windows(4).position(|w| w == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = core::str::from_utf8(&buf[..end]).map_err(|_| HttpError::InvalidResponse)?;
+    let mut lines = head.split("\r\n");
+
+    let status_line = lines.next().unwrap_or_default();
+    let mut parts = status_line.splitn(3, ' ');
+    let version = parts.next().unwrap_or_default();
+    let status = parts.next().unwrap_or_default();
+    if !version.starts_with("HTTP/1.") || status.len() != 3 {
+        return Err(HttpError::InvalidResponse);
+    }
+    let status = status.parse().map_err(|_| HttpError::InvalidResponse)?;
+
+    let headers = lines
+        .map(|line| {
+            let (name, value) = line.split_once(':').ok_or(HttpError::InvalidResponse)?;
+            if !is_token(name) {
+                return Err(HttpError::InvalidResponse);
+            }
+            Ok(Header {
+                name: String::from(name),
+                value: String::from(value.trim()),
+            })
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Some(ResponseHead {
+        status,
+        headers,
+        len: end + 4,
+    }))
+}
+
+/// How the end of a response body is found.
+pub(crate) enum Framing {
+    Empty,
+    Length(u64),
+    Chunked,
+    UntilClose,
+}
+
+/// Determines the framing of the body of a response to `method` (RFC 9112, section 6.3).
+pub(crate) fn framing(method: Method, head: &ResponseHead) -> Result<Framing, HttpError> {
+    if method == Method::Head || head.status / 100 == 1 || matches!(head.status, 204 | 304) {
+        return Ok(Framing::Empty);
+    }
+    let header = |name: &str| {
+        head.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case(name))
+            .map(|header| header.value.as_str())
+    };
+    if let Some(encoding) = header("transfer-encoding") {
+        // Chunked has to be the last coding, other codings are not decoded
+        return match encoding.rsplit(',').next().map(str::trim) {
+            Some(last) if last.eq_ignore_ascii_case("chunked") => Ok(Framing::Chunked),
+            _ => Ok(Framing::UntilClose),
+        };
+    }
+    match header("content-length") {
+        Some(len) => len
+            .parse()
+            .map(Framing::Length)
+            .map_err(|_| HttpError::InvalidResponse),
+        None => Ok(Framing::UntilClose),
+    }
+}
+
+/// Parses the size line of a chunk, ignoring chunk extensions.
+pub(crate) fn chunk_size(line: &[u8]) -> Result<u64, HttpError> {
+    let line = core::str::from_utf8(line).map_err(|_| HttpError::InvalidResponse)?;
+    let size = line.split(';').next().unwrap_or_default().trim();
+    u64::from_str_radix(size, 16).map_err(|_| HttpError::InvalidResponse)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url_parts(url: &str) -> Result<(&str, u16, &str, &str), HttpError> {
+        parse_url(url).map(|url| (url.host, url.port, url.authority, url.target))
+    }
+
+    #[test]
+    fn parses_urls() {
+        assert_eq!(
+            url_parts("http://example.org"),
+            Ok(("example.org", 80, "example.org", ""))
+        );
+        assert_eq!(
+            url_parts("HTTP://example.org:8080/a/b?c=d#e"),
+            Ok(("example.org", 8080, "example.org:8080", "/a/b?c=d"))
+        );
+        assert_eq!(
+            url_parts("http://192.0.2.1?q"),
+            Ok(("192.0.2.1", 80, "192.0.2.1", "?q"))
+        );
+        assert_eq!(
+            url_parts("http://[2001:db8::1]/"),
+            Ok(("2001:db8::1", 80, "[2001:db8::1]", "/"))
+        );
+        assert_eq!(
+            url_parts("http://[2001:db8::1]:8080"),
+            Ok(("2001:db8::1", 8080, "[2001:db8::1]:8080", ""))
+        );
+    }
+
+    #[test]
+    fn rejects_invalid_urls() {
+        for url in [
+            "example.org",
+            "http://",
+            "http://:80/",
+            "http://user@example.org/",
+            "http://example.org:/",
+            "http://example.org:+80/",
+            "http://example.org:65536/",
+            "http://example.org:80:80/",
+            "http://[2001:db8::1/",
+            "http://[2001:db8::1]80/",
+            "http://[2001:db8::1]x:80/",
+            "http://[2001:db8::1]:/",
+            "http://[]/",
+            "http://example.org\r\nX-Injected: 1/",
+            "http://exa\u{0}mple.org/",
+            "http://example.org\t/",
+            "http://example.org/a b",
+            "http://example.org/\u{7f}",
+        ] {
+            assert_eq!(parse_url(url).err(), Some(HttpError::InvalidUrl), "{url:?}");
+        }
+        assert_eq!(
+            parse_url("https://example.org/").err(),
+            Some(HttpError::UnsupportedScheme)
+        );
+    }
+
+    #[test]
+    fn parses_literal_addresses() {
+        assert_eq!(
+            literal_address("192.0.2.1"),
+            Ok(Some(IpAddress::Ipv4(core::net::Ipv4Addr::new(
+                192, 0, 2, 1
+            ))))
+        );
+        assert_eq!(literal_address("example.org"), Ok(None));
+        #[cfg(not(feature = "ipv6"))]
+        assert_eq!(
+            literal_address("2001:db8::1"),
+            Err(HttpError::UnsupportedAddress)
+        );
+    }
+
+    #[test]
+    fn parses_response_heads() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Type: text/plain\r\nX-Empty:\r\n\r\nbody";
+        let head = parse_response_head(response).unwrap().unwrap();
+        assert_eq!(head.status, 200);
+        assert_eq!(head.len, response.len() - 4);
+        let headers: Vec<_> = head
+            .headers
+            .iter()
+            .map(|header| (header.name.as_str(), header.value.as_str()))
+            .collect();
+        assert_eq!(headers, [("Content-Type", "text/plain"), ("X-Empty", "")]);
+
+        // Without reason phrase
+        let head = parse_response_head(b"HTTP/1.0 404\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(head.status, 404);
+    }
+
+    #[test]
+    fn waits_for_complete_response_heads() {
+        let response = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        for len in 0..response.len() {
+            assert!(parse_response_head(&response[..len]).unwrap().is_none());
+        }
+    }
+
+    #[test]
+    fn rejects_invalid_response_heads() {
+        for response in [
+            &b"HTTP/2 200 OK\r\n\r\n"[..],
+            b"HTTP/1.1 20 OK\r\n\r\n",
+            b"HTTP/1.1 2000 OK\r\n\r\n",
+            b"HTTP/1.1 abc OK\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nNo colon\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\nBad name: value\r\n\r\n",
+            b"HTTP/1.1 200 OK\r\n: value\r\n\r\n",
+            b"HTTP/1.1 200 \xff\r\n\r\n",
+        ] {
+            assert_eq!(
+                parse_response_head(response).err(),
+                Some(HttpError::InvalidResponse),
+                "{response:?}"
+            );
+        }
+    }
+
+    fn head(status: u16, headers: &[(&str, &str)]) -> ResponseHead {
+        ResponseHead {
+            status,
+            headers: headers
+                .iter()
+                .map(|&(name, value)| Header {
+                    name: String::from(name),
+                    value: String::from(value),
+                })
+                .collect(),
+            len: 0,
+        }
+    }
+
+    #[test]
+    fn determines_framing() {
+        let length = head(200, &[("Content-Length", "42")]);
+        assert!(matches!(
+            framing(Method::Get, &length),
+            Ok(Framing::Length(42))
+        ));
+        assert!(matches!(framing(Method::Head, &length), Ok(Framing::Empty)));
+        for status in [101, 204, 304] {
+            let head = head(status, &[("Content-Length", "42")]);
+            assert!(matches!(framing(Method::Get, &head), Ok(Framing::Empty)));
+        }
+
+        // Transfer-Encoding takes precedence over Content-Length
+        let chunked = head(
+            200,
+            &[
+                ("content-length", "42"),
+                ("Transfer-Encoding", "gzip, Chunked"),
+            ],
+        );
+        assert!(matches!(
+            framing(Method::Get, &chunked),
+            Ok(Framing::Chunked)
+        ));
+        let not_chunked = head(200, &[("Transfer-Encoding", "chunked, gzip")]);
+        assert!(matches!(
+            framing(Method::Get, &not_chunked),
+            Ok(Framing::UntilClose)
+        ));
+        assert!(matches!(
+            framing(Method::Post, &head(200, &[])),
+            Ok(Framing::UntilClose)
+        ));
+
+        for len in ["-1", "4 2", "0x10", ""] {
+            assert_eq!(
+                framing(Method::Get, &head(200, &[("Content-Length", len)])).err(),
+                Some(HttpError::InvalidResponse)
+            );
+        }
+    }
+
+    #[test]
+    fn parses_chunk_sizes() {
+        assert_eq!(chunk_size(b"0"), Ok(0));
+        assert_eq!(chunk_size(b"1aF"), Ok(0x1af));
+        assert_eq!(chunk_size(b"10;name=value"), Ok(16));
+        assert_eq!(chunk_size(b"ffffffffffffffff"), Ok(u64::MAX));
+        for line in [&b""[..], b"g", b"10000000000000000", b"0x10", b"\xff"] {
+            assert_eq!(chunk_size(line), Err(HttpError::InvalidResponse));
+        }
+    }
+}
@@ -0,0 +1,439 @@
+mod message;
+
+use ariel_os_embassy::api::time::{Duration, Timer};
+use ariel_os_embassy::reexports::embassy_net::IpEndpoint;
+use ariel_os_embassy::reexports::embassy_net::tcp::TcpSocket;
+
+use embassy_futures::select::{Either, select};
+
+use wasmtime::component::{Resource, bindgen};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use super::ArielOSHost;
+use super::dns::DnsError;
+use super::local::Local;
+use super::net_policy::PolicyError;
+use super::tcp::{ArielTcpHost, TcpError};
+
+use message::Framing;
+
+bindgen!({
+    world: "ariel:wasm-bindings/http-client",
+    path: "../../wit/",
+    imports: {
+        "ariel:wasm-bindings/http-client-api.send": async,
+        "ariel:wasm-bindings/http-client-api.[method]response.read-body": async,
+    }
+});
+
+pub use ariel::wasm_bindings::http_client_api::{
+    self as gen_http_client, Header, Host, HostResponse, HostWithStore, HttpError, Method, Request,
+    Response, add_to_linker,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Longest chunk size line or trailer line accepted from the server.
+const MAX_LINE_LEN: usize = 256;
+
+impl From<PolicyError> for HttpError {
+    fn from(value: PolicyError) -> Self {
+        match value {
+            PolicyError::Denied => HttpError::PermissionDenied,
+            PolicyError::RateLimited => HttpError::RateLimited,
+        }
+    }
+}
+
+impl From<TcpError> for HttpError {
+    fn from(value: TcpError) -> Self {
+        match value {
+            TcpError::NoBuffers => HttpError::NoBuffers,
+            _ => HttpError::ConnectionFailed,
+        }
+    }
+}
+
+impl From<DnsError> for HttpError {
+    fn from(value: DnsError) -> Self {
+        match value {
+            DnsError::InvalidName => HttpError::InvalidUrl,
+            _ => HttpError::DnsFailed,
+        }
+    }
+}
+
+/// Where the body of a response is at.
+enum BodyState {
+    Length(u64),
+    UntilClose,
+    ChunkSize,
+    ChunkData(u64),
+    /// The line ending after the data of a chunk.
+    ChunkEnd,
+    Trailers,
+    Done,
+}
+
+/// A response whose body is still being received.
+struct Exchange {
+    socket: Local<TcpSocket<'static>>,
+    status: u16,
+    headers: Vec<Header>,
+    /// Received bytes not returned yet.
+    pending: Vec<u8>,
+    body: BodyState,
+    timeout: Duration,
+}
+
+/// Reads what `socket` has received into `pending`, returning `false` once the server closed
+/// the connection.
+async fn receive(
+    socket: &mut Local<TcpSocket<'static>>,
+    pending: &mut Vec<u8>,
+    timeout: Duration,
+) -> Result<bool, HttpError> {
+    let mut chunk = [0; 256];
+    match select(
+        socket.run(|socket| socket.read(&mut chunk)),
+        Timer::after(timeout),
+    )
+    .await
+    {
+        Either::First(Ok(0)) => Ok(false),
+        Either::First(Ok(n)) => {
+            pending.extend_from_slice(&chunk[..n]);
+            Ok(true)
+        }
+        Either::First(Err(_)) => Err(HttpError::Disconnected),
+        Either::Second(()) => Err(HttpError::TimedOut),
+    }
+}
+
+/// Writes `data` to `socket`, each write waiting at most `timeout` for the server to make room.
+async fn write_all(
+    socket: &mut Local<TcpSocket<'static>>,
+    mut data: &[u8],
+    timeout: Duration,
+) -> Result<(), HttpError> {
+    while !data.is_empty() {
+        match select(
+            socket.run(|socket| socket.write(data)),
+            Timer::after(timeout),
+        )
+        .await
+        {
+            Either::First(Ok(n)) if n > 0 => data = &data[n..],
+            Either::First(_) => return Err(HttpError::Disconnected),
+            Either::Second(()) => return Err(HttpError::TimedOut),
+        }
+    }
+    Ok(())
+}
+
+/// Sends the request and receives the head of the response.
+async fn exchange_head(
+    socket: &mut Local<TcpSocket<'static>>,
+    server: IpEndpoint,
+    method: Method,
+    head: &[u8],
+    body: Option<Vec<u8>>,
+    timeout: Duration,
+) -> Result<(u16, Vec<Header>, Vec<u8>, BodyState), HttpError> {
+    match select(
+        socket.run(|socket| socket.connect(server)),
+        Timer::after(timeout),
+    )
+    .await
+    {
+        Either::First(Ok(())) => {}
+        Either::First(Err(_)) => return Err(HttpError::ConnectionFailed),
+        Either::Second(()) => return Err(HttpError::TimedOut),
+    }
+    write_all(socket, head, timeout).await?;
+    if let Some(body) = body {
+        write_all(socket, &body, timeout).await?;
+    }
+
+    let mut pending = Vec::new();
+    loop {
+        match message::parse_response_head(&pending)? {
+            // Interim responses, e.g. 100 Continue, precede the final one
+            Some(head) if (100..200).contains(&head.status) => {
+                pending.drain(..head.len);
+            }
+            Some(head) => {
+                let body = match message::framing(method, &head)? {
+                    Framing::Empty => BodyState::Done,
+                    Framing::Length(len) => BodyState::Length(len),
+                    Framing::Chunked => BodyState::ChunkSize,
+                    Framing::UntilClose => BodyState::UntilClose,
+                };
+                pending.drain(..head.len);
+                return Ok((head.status, head.headers, pending, body));
+            }
+            None if pending.len() > message::MAX_HEAD_LEN => {
+                return Err(HttpError::InvalidResponse);
+            }
+            None => {
+                if !receive(socket, &mut pending, timeout).await? {
+                    return Err(HttpError::Disconnected);
+                }
+            }
+        }
+    }
+}
+
+impl Exchange {
+    async fn fill(&mut self) -> Result<bool, HttpError> {
+        receive(&mut self.socket, &mut self.pending, self.timeout).await
+    }
+
+    /// Reads a line of the chunked framing, without its line ending.
+    async fn read_line(&mut self) -> Result<Vec<u8>, HttpError> {
+        loop {
+            if let Some(end) = self.pending.windows(2).position(|w| w == b"\r\n") {
+                let mut line: Vec<u8> = self.pending.drain(..end + 2).collect();
+                line.truncate(end);
+                return Ok(line);
+            }
+            if self.pending.len() > MAX_LINE_LEN {
+                return Err(HttpError::InvalidResponse);
+            }
+            if !self.fill().await? {
+                return Err(HttpError::Disconnected);
+            }
+        }
+    }
+
+    /// Returns up to `max_len` received bytes, or nothing once the server closed the connection.
+    async fn read_raw(&mut self, max_len: usize) -> Result<Vec<u8>, HttpError> {
+        if self.pending.is_empty() && !self.fill().await? {
+            return Ok(Vec::new());
+        }
+        let n = max_len.min(self.pending.len());
+        Ok(self.pending.drain(..n).collect())
+    }
+
+    async fn read_body(&mut self, max_len: usize) -> Result<Vec<u8>, HttpError> {
+        if max_len == 0 {
+            return Ok(Vec::new());
+        }
+        loop {
+            match self.body {
+                BodyState::Done => return Ok(Vec::new()),
+                BodyState::Length(0) => self.body = BodyState::Done,
+                BodyState::Length(remaining) | BodyState::ChunkData(remaining) => {
+                    let len = usize::try_from(remaining).unwrap_or(usize::MAX);
+                    let data = self.read_raw(max_len.min(len)).await?;
+                    if data.is_empty() {
+                        return Err(HttpError::Disconnected);
+                    }
+                    let remaining = remaining - data.len() as u64;
+                    self.body = match self.body {
+                        BodyState::ChunkData(_) if remaining == 0 => BodyState::ChunkEnd,
+                        BodyState::ChunkData(_) => BodyState::ChunkData(remaining),
+                        _ => BodyState::Length(remaining),
+                    };
+                    return Ok(data);
+                }
+                BodyState::UntilClose => {
+                    let data = self.read_raw(max_len).await?;
+                    if data.is_empty() {
+                        self.body = BodyState::Done;
+                    }
+                    return Ok(data);
+                }
+                BodyState::ChunkSize => {
+                    let size = message::chunk_size(&self.read_line().await?)?;
+                    self.body = if size == 0 {
+                        BodyState::Trailers
+                    } else {
+                        BodyState::ChunkData(size)
+                    };
+                }
+                BodyState::ChunkEnd => {
+                    if !self.read_line().await?.is_empty() {
+                        return Err(HttpError::InvalidResponse);
+                    }
+                    self.body = BodyState::ChunkSize;
+                }
+                BodyState::Trailers => {
+                    // Trailer fields are dropped, an empty line ends them
+                    if self.read_line().await?.is_empty() {
+                        self.body = BodyState::Done;
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// HTTP requests of a capsule.
+///
+/// Requests use the sockets of the TCP host and are subject to its policy, host names are
+/// resolved through the DNS host. The representation of a [`Response`] resource is the index
+/// of the exchange in `exchanges`.
+pub(crate) struct ArielHttpClientHost {
+    exchanges: Vec<Option<Exchange>>,
+    /// Socket of the request being sent.
+    ///
+    /// It stays here until the head of the response arrived, so a request cut short by the
+    /// deadline of the capsule doesn't lose it: the next `send` returns it to the TCP host.
+    sending: Option<Local<TcpSocket<'static>>>,
+    timeout: Duration,
+}
+
+impl Default for ArielHttpClientHost {
+    fn default() -> Self {
+        Self {
+            exchanges: Vec::new(),
+            sending: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl ArielHttpClientHost {
+    fn exchange(&mut self, handle: &Resource<Response>) -> &mut Exchange {
+        // Handles only come from `send` and are removed on drop
+        self.exchanges[handle.rep() as usize]
+            .as_mut()
+            .expect("Response used after being dropped")
+    }
+
+    async fn send(
+        &mut self,
+        tcp: &mut ArielTcpHost,
+        server: IpEndpoint,
+        method: Method,
+        head: Vec<u8>,
+        body: Option<Vec<u8>>,
+    ) -> Result<Resource<Response>, HttpError> {
+        if let Some(socket) = self.sending.take() {
+            tcp.release(socket);
+        }
+        tcp.policy.check_remote(&server)?;
+        tcp.policy
+            .consume(head.len() + body.as_ref().map_or(0, Vec::len))?;
+        let socket = self.sending.insert(tcp.lease()?);
+        let result = exchange_head(socket, server, method, &head, body, self.timeout).await;
+        let socket = self.sending.take().expect("The request holds its socket");
+        let (status, headers, pending, body) = match result {
+            Ok(received) => received,
+            Err(err) => {
+                tcp.release(socket);
+                return Err(err);
+            }
+        };
+
+        let exchange = Exchange {
+            socket,
+            status,
+            headers,
+            pending,
+            body,
+            timeout: self.timeout,
+        };
+        let index = match self.exchanges.iter().position(Option::is_none) {
+            Some(index) => {
+                self.exchanges[index] = Some(exchange);
+                index
+            }
+            None => {
+                self.exchanges.push(Some(exchange));
+                self.exchanges.len() - 1
+            }
+        };
+        Ok(Resource::new_own(index as u32))
+    }
+
+    fn drop(&mut self, tcp: &mut ArielTcpHost, rep: Resource<Response>) {
+        if let Some(exchange) = self
+            .exchanges
+            .get_mut(rep.rep() as usize)
+            .and_then(Option::take)
+        {
+            tcp.release(exchange.socket);
+        }
+    }
+}
+
+impl Host for ArielOSHost {
+    async fn send(&mut self, request: Request) -> Result<Resource<Response>, HttpError> {
+        self.before_deadline(|host| host.request(request), Err(HttpError::TimedOut))
+            .await
+    }
+}
+
+impl ArielOSHost {
+    /// Resolves the server of `request`, then sends it.
+    async fn request(&mut self, request: Request) -> Result<Resource<Response>, HttpError> {
+        let Request {
+            method,
+            url,
+            headers,
+            body,
+        } = request;
+        let url = message::parse_url(&url)?;
+        let head = message::request_head(method, &url, &headers, body.as_ref().map(Vec::len))?;
+        let addr = match message::literal_address(url.host)? {
+            Some(addr) => addr,
+            None => *self
+                .dns_host
+                .lookup(url.host)
+                .await?
+                .first()
+                .ok_or(HttpError::DnsFailed)?,
+        };
+        self.http_client_host
+            .send(
+                &mut self.tcp_host,
+                IpEndpoint::new(addr, url.port),
+                method,
+                head,
+                body,
+            )
+            .await
+    }
+}
+
+impl HostResponse for ArielOSHost {
+    fn status(&mut self, self_: Resource<Response>) -> u16 {
+        self.http_client_host.exchange(&self_).status
+    }
+
+    fn headers(&mut self, self_: Resource<Response>) -> Vec<Header> {
+        self.http_client_host.exchange(&self_).headers.clone()
+    }
+
+    async fn read_body(
+        &mut self,
+        self_: Resource<Response>,
+        max_len: u32,
+    ) -> Result<Vec<u8>, HttpError> {
+        self.before_deadline(
+            |host| {
+                host.http_client_host
+                    .exchange(&self_)
+                    .read_body(max_len as usize)
+            },
+            Err(HttpError::TimedOut),
+        )
+        .await
+    }
+
+    fn drop(&mut self, rep: Resource<Response>) -> wasmtime::Result<()> {
+        self.http_client_host.drop(&mut self.tcp_host, rep);
+        Ok(())
+    }
+}
+
+impl ArielOSHost {
+    /// Sets how long connecting and each read from the server may take.
+    pub fn set_http_client_timeout(&mut self, timeout: Duration) {
+        self.http_client_host.timeout = timeout;
+    }
+}
@@ -0,0 +1,230 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use ariel_os_embassy::i2c::controller::I2cDevice;
+
+use embedded_hal::i2c::{Error as _, ErrorKind};
+use embedded_hal_async::i2c::{I2c as _, Operation};
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/i2c",
+    path: "../../wit/",
+    imports: {
+        "ariel:wasm-bindings/i2c-api.write": async,
+        "ariel:wasm-bindings/i2c-api.read": async,
+        "ariel:wasm-bindings/i2c-api.write-read": async,
+        "ariel:wasm-bindings/i2c-api.transaction": async,
+    }
+});
+
+pub use ariel::wasm_bindings::i2c_api::{self as gen_i2c, Host, HostWithStore, add_to_linker};
+
+use gen_i2c::I2cError;
+
+/// Highest valid 7-bit I2C address.
+const MAX_ADDRESS: u8 = 0x7f;
+
+const DEFAULT_MAX_READ_LEN: usize = 256;
+
+pub(crate) struct ArielI2cHost {
+    pub(crate) device: Option<I2cDevice>,
+    /// 7-bit addresses the capsule is allowed to talk to.
+    pub(crate) allowed_addresses: Vec<u8>,
+    /// Most bytes read by a single call, the buffers are allocated by the host.
+    max_read_len: usize,
+}
+
+impl Default for ArielI2cHost {
+    fn default() -> Self {
+        Self {
+            device: None,
+            allowed_addresses: Vec::new(),
+            max_read_len: DEFAULT_MAX_READ_LEN,
+        }
+    }
+}
+
+/// Zeroed buffer for a read of `len` bytes, if that is at most `max_len`.
+fn read_buffer(len: u32, max_len: usize) -> Result<Vec<u8>, I2cError> {
+    if len as usize > max_len {
+        return Err(I2cError::TooLong);
+    }
+    Ok(core::iter::repeat_n(0, len as usize).collect())
+}
+
+impl ArielI2cHost {
+    /// Returns the bus if `address` may be used by the capsule.
+    fn device_for(&mut self, address: u8) -> Result<&mut I2cDevice, I2cError> {
+        if address > MAX_ADDRESS || !self.allowed_addresses.contains(&address) {
+            return Err(I2cError::NotAllowed);
+        }
+        self.device.as_mut().ok_or(I2cError::NoBus)
+    }
+}
+
+impl From<ErrorKind> for I2cError {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::NoAcknowledge(_) => I2cError::NoAcknowledge,
+            ErrorKind::ArbitrationLoss => I2cError::ArbitrationLoss,
+            ErrorKind::Overrun => I2cError::Overrun,
+            ErrorKind::Bus => I2cError::Bus,
+            _ => I2cError::Other,
+        }
+    }
+}
+
+impl Host for ArielI2cHost {
+    async fn write(&mut self, address: u8, data: Vec<u8>) -> Result<(), I2cError> {
+        let device = self.device_for(address)?;
+        device
+            .write(address, &data)
+            .await
+            .map_err(|e| e.kind().into())
+    }
+
+    async fn read(&mut self, address: u8, len: u32) -> Result<Vec<u8>, I2cError> {
+        let max_len = self.max_read_len;
+        let device = self.device_for(address)?;
+        let mut buf = read_buffer(len, max_len)?;
+        device
+            .read(address, &mut buf)
+            .await
+            .map_err(|e| I2cError::from(e.kind()))?;
+        Ok(buf)
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        data: Vec<u8>,
+        len: u32,
+    ) -> Result<Vec<u8>, I2cError> {
+        let max_len = self.max_read_len;
+        let device = self.device_for(address)?;
+        let mut buf = read_buffer(len, max_len)?;
+        device
+            .write_read(address, &data, &mut buf)
+            .await
+            .map_err(|e| I2cError::from(e.kind()))?;
+        Ok(buf)
+    }
+
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: Vec<gen_i2c::Operation>,
+    ) -> Result<Vec<Vec<u8>>, I2cError> {
+        let mut budget = self.max_read_len;
+        let device = self.device_for(address)?;
+
+        // Write operations keep their data as buffer, read operations get a zeroed one, all
+        // reads together are bounded like a single one.
+        let mut is_read = Vec::with_capacity(operations.len());
+        let mut buffers: Vec<Vec<u8>> = operations
+            .into_iter()
+            .map(|op| match op {
+                gen_i2c::Operation::Read(len) => {
+                    is_read.push(true);
+                    let buf = read_buffer(len, budget)?;
+                    budget -= buf.len();
+                    Ok(buf)
+                }
+                gen_i2c::Operation::Write(data) => {
+                    is_read.push(false);
+                    Ok(data)
+                }
+            })
+            .collect::<Result<_, I2cError>>()?;
+
+        let mut ops: Vec<Operation<'_>> = buffers
+            .iter_mut()
+            .zip(is_read.iter())
+            .map(|(buf, read)| {
+                if *read {
+                    Operation::Read(buf.as_mut_slice())
+                } else {
+                    Operation::Write(buf.as_slice())
+                }
+            })
+            .collect();
+
+        device
+            .transaction(address, &mut ops)
+            .await
+            .map_err(|e| I2cError::from(e.kind()))?;
+        drop(ops);
+
+        Ok(buffers
+            .into_iter()
+            .zip(is_read)
+            .filter_map(|(buf, read)| read.then_some(buf))
+            .collect())
+    }
+}
+
+impl Host for ArielOSHost {
+    async fn write(&mut self, address: u8, data: Vec<u8>) -> Result<(), I2cError> {
+        self.before_deadline(
+            |host| host.i2c_host.write(address, data),
+            Err(I2cError::TimedOut),
+        )
+        .await
+    }
+
+    async fn read(&mut self, address: u8, len: u32) -> Result<Vec<u8>, I2cError> {
+        self.before_deadline(
+            |host| host.i2c_host.read(address, len),
+            Err(I2cError::TimedOut),
+        )
+        .await
+    }
+
+    async fn write_read(
+        &mut self,
+        address: u8,
+        data: Vec<u8>,
+        len: u32,
+    ) -> Result<Vec<u8>, I2cError> {
+        self.before_deadline(
+            |host| host.i2c_host.write_read(address, data, len),
+            Err(I2cError::TimedOut),
+        )
+        .await
+    }
+
+    async fn transaction(
+        &mut self,
+        address: u8,
+        operations: Vec<gen_i2c::Operation>,
+    ) -> Result<Vec<Vec<u8>>, I2cError> {
+        self.before_deadline(
+            |host| host.i2c_host.transaction(address, operations),
+            Err(I2cError::TimedOut),
+        )
+        .await
+    }
+}
+
+impl ArielOSHost {
+    /// Gives the capsule access to `device`, restricted to the given 7-bit addresses.
+    ///
+    /// Addresses that do not fit in 7 bits are ignored.
+    pub fn bind_i2c(&mut self, device: I2cDevice, allowed_addresses: &[u8]) {
+        self.i2c_host.device = Some(device);
+        self.i2c_host.allowed_addresses = allowed_addresses
+            .iter()
+            .copied()
+            .filter(|a| *a <= MAX_ADDRESS)
+            .collect();
+    }
+
+    /// Sets how many bytes a capsule may read from the bus in a single call.
+    pub fn set_i2c_max_read_len(&mut self, len: usize) {
+        self.i2c_host.max_read_len = len;
+    }
+}
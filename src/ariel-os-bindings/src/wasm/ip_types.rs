@@ -0,0 +1,170 @@
+use ariel_os_embassy::reexports::embassy_net::{IpAddress, IpEndpoint};
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/ip",
+    path: "../../wit/",
+});
+
+pub use ariel::wasm_bindings::ip_types::{
+    Endpoint, Host, HostWithStore, IpAddr, Ipv4Addr, Ipv6Addr, add_to_linker,
+};
+
+/// The address family isn't supported (IPv6 without the `ipv6` feature).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct UnsupportedAddress;
+
+impl TryFrom<IpAddress> for IpAddr {
+    type Error = UnsupportedAddress;
+
+    fn try_from(t: IpAddress) -> Result<Self, UnsupportedAddress> {
+        match t {
+            IpAddress::Ipv4(addr) => {
+                let [a, b, c, d] = addr.octets();
+                Ok(IpAddr::V4(Ipv4Addr { a, b, c, d }))
+            }
+            #[cfg(feature = "ipv6")]
+            IpAddress::Ipv6(addr) => {
+                let [a, b, c, d, e, f, g, h] = addr.segments();
+                Ok(IpAddr::V6(Ipv6Addr {
+                    a,
+                    b,
+                    c,
+                    d,
+                    e,
+                    f,
+                    g,
+                    h,
+                }))
+            }
+            // The network stack may have IPv6 enabled without the `ipv6` feature
+            #[allow(unreachable_patterns, reason = "Conditional compilation")]
+            _ => Err(UnsupportedAddress),
+        }
+    }
+}
+
+impl TryFrom<IpAddr> for IpAddress {
+    type Error = UnsupportedAddress;
+
+    fn try_from(t: IpAddr) -> Result<Self, UnsupportedAddress> {
+        match t {
+            IpAddr::V4(Ipv4Addr { a, b, c, d }) => Ok(Self::v4(a, b, c, d)),
+            #[cfg(feature = "ipv6")]
+            IpAddr::V6(Ipv6Addr {
+                a,
+                b,
+                c,
+                d,
+                e,
+                f,
+                g,
+                h,
+            }) => Ok(Self::v6(a, b, c, d, e, f, g, h)),
+            #[cfg(not(feature = "ipv6"))]
+            IpAddr::V6(_) => Err(UnsupportedAddress),
+        }
+    }
+}
+
+impl TryFrom<IpEndpoint> for Endpoint {
+    type Error = UnsupportedAddress;
+
+    fn try_from(t: IpEndpoint) -> Result<Self, UnsupportedAddress> {
+        Ok(Self {
+            addr: t.addr.try_into()?,
+            port: t.port,
+        })
+    }
+}
+
+impl TryFrom<Endpoint> for IpEndpoint {
+    type Error = UnsupportedAddress;
+
+    fn try_from(t: Endpoint) -> Result<Self, UnsupportedAddress> {
+        Ok(Self::new(t.addr.try_into()?, t.port))
+    }
+}
+
+impl Host for ArielOSHost {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(addr: IpAddress) -> Result<IpAddress, UnsupportedAddress> {
+        IpAddress::try_from(IpAddr::try_from(addr)?)
+    }
+
+    #[test]
+    fn converts_v4() {
+        let addr = IpAddress::v4(192, 0, 2, 1);
+        assert!(matches!(
+            IpAddr::try_from(addr),
+            Ok(IpAddr::V4(Ipv4Addr {
+                a: 192,
+                b: 0,
+                c: 2,
+                d: 1
+            }))
+        ));
+        assert_eq!(round_trip(addr), Ok(addr));
+    }
+
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn converts_v6() {
+        let addr = IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+        assert!(matches!(
+            IpAddr::try_from(addr),
+            Ok(IpAddr::V6(Ipv6Addr {
+                a: 0x2001,
+                b: 0xdb8,
+                c: 0,
+                d: 0,
+                e: 0,
+                f: 0,
+                g: 0,
+                h: 1
+            }))
+        ));
+        assert_eq!(round_trip(addr), Ok(addr));
+    }
+
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn keeps_v4_mapped_v6() {
+        // ::ffff:192.0.2.1 stays an IPv6 address, the capsule replies to it as such
+        let addr = IpAddress::v6(0, 0, 0, 0, 0, 0xffff, 0xc000, 0x0201);
+        assert!(matches!(IpAddr::try_from(addr), Ok(IpAddr::V6(_))));
+        assert_eq!(round_trip(addr), Ok(addr));
+    }
+
+    #[cfg(not(feature = "ipv6"))]
+    #[test]
+    fn rejects_v6_without_ipv6() {
+        let addr = IpAddr::V6(Ipv6Addr {
+            a: 0x2001,
+            b: 0xdb8,
+            c: 0,
+            d: 0,
+            e: 0,
+            f: 0,
+            g: 0,
+            h: 1,
+        });
+        assert_eq!(IpAddress::try_from(addr), Err(UnsupportedAddress));
+    }
+
+    #[test]
+    fn converts_endpoints() {
+        let endpoint = IpEndpoint::new(IpAddress::v4(192, 0, 2, 1), 5683);
+        assert_eq!(
+            IpEndpoint::try_from(Endpoint::try_from(endpoint).unwrap()),
+            Ok(endpoint)
+        );
+    }
+}
@@ -0,0 +1,245 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+use embassy_sync::signal::Signal;
+
+use ariel_os_embassy::api::time::{Duration, Timer};
+
+use zeroize::Zeroizing;
+
+use super::ArielOSHost;
+use super::crypto::{Algorithm, KeyMaterial};
+
+/// Storage key the persistent keys are saved under.
+pub const STORAGE_KEY: &str = "ariel-os-bindings.keys";
+
+/// Delay before a failed write to flash is tried again.
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KeyStoreError {
+    /// Reading or writing the flash storage failed.
+    Storage,
+    /// The persisted keys couldn't be decoded.
+    Corrupted,
+    /// The name of the key is longer than 255 bytes.
+    NameTooLong,
+}
+
+/// Identifier of `algorithm` in the persisted keys and in the provisioning requests of
+/// [`KeyStoreHandler`](super::coap::KeyStoreHandler).
+pub fn algorithm_id(algorithm: Algorithm) -> u8 {
+    match algorithm {
+        Algorithm::HmacSha256 => 0,
+        Algorithm::Aes128Ccm8 => 1,
+        Algorithm::Aes128Gcm => 2,
+        Algorithm::Aes256Gcm => 3,
+        Algorithm::Ed25519 => 4,
+    }
+}
+
+pub fn algorithm_from_id(id: u8) -> Option<Algorithm> {
+    match id {
+        0 => Some(Algorithm::HmacSha256),
+        1 => Some(Algorithm::Aes128Ccm8),
+        2 => Some(Algorithm::Aes128Gcm),
+        3 => Some(Algorithm::Aes256Gcm),
+        4 => Some(Algorithm::Ed25519),
+        _ => None,
+    }
+}
+
+struct StoredKey {
+    name: String,
+    material: KeyMaterial,
+    /// Whether the key was provisioned at runtime and has to be saved to flash.
+    persistent: bool,
+}
+
+/// Keys held by the host, that capsules can only use through the handles of `crypto-api`.
+///
+/// Keys are provisioned either by the firmware at build time with [`KeyStore::provision`], or at
+/// runtime with [`KeyStore::store`] (e.g. by a [`KeyStoreHandler`](super::coap::KeyStoreHandler)),
+/// in which case they are saved to flash by [`KeyStore::run_persistence`] and restored by
+/// [`KeyStore::load`].
+///
+/// A capsule can only open the keys it was granted with [`ArielOSHost::bind_key_store`].
+pub struct KeyStore {
+    keys: Mutex<CriticalSectionRawMutex, RefCell<Vec<StoredKey>>>,
+    dirty: Signal<CriticalSectionRawMutex, ()>,
+}
+
+impl KeyStore {
+    pub const fn new() -> Self {
+        Self {
+            keys: Mutex::new(RefCell::new(Vec::new())),
+            dirty: Signal::new(),
+        }
+    }
+
+    fn insert(
+        &self,
+        name: &str,
+        material: KeyMaterial,
+        persistent: bool,
+    ) -> Result<(), KeyStoreError> {
+        // The persisted keys store the length of the name in a byte
+        if name.len() > usize::from(u8::MAX) {
+            return Err(KeyStoreError::NameTooLong);
+        }
+        self.keys.lock(|keys| {
+            let mut keys = keys.borrow_mut();
+            let key = StoredKey {
+                name: String::from(name),
+                material,
+                persistent,
+            };
+            match keys.iter_mut().find(|k| k.name == name) {
+                Some(existing) => *existing = key,
+                None => keys.push(key),
+            }
+        });
+        Ok(())
+    }
+
+    /// Adds a key that is part of the firmware, it isn't saved to flash.
+    ///
+    /// This replaces any key of the same name.
+    pub fn provision(&self, name: &str, material: KeyMaterial) -> Result<(), KeyStoreError> {
+        self.insert(name, material, false)
+    }
+
+    /// Adds a key and schedules saving it to flash.
+    ///
+    /// This replaces any key of the same name.
+    pub fn store(&self, name: &str, material: KeyMaterial) -> Result<(), KeyStoreError> {
+        self.insert(name, material, true)?;
+        self.dirty.signal(());
+        Ok(())
+    }
+
+    /// Removes a key, returns whether it existed.
+    pub fn remove(&self, name: &str) -> bool {
+        let removed = self.keys.lock(|keys| {
+            let mut keys = keys.borrow_mut();
+            let len = keys.len();
+            keys.retain(|k| k.name != name);
+            keys.len() != len
+        });
+        if removed {
+            self.dirty.signal(());
+        }
+        removed
+    }
+
+    pub fn algorithm(&self, name: &str) -> Option<Algorithm> {
+        self.keys.lock(|keys| {
+            keys.borrow()
+                .iter()
+                .find(|k| k.name == name)
+                .map(|k| k.material.algorithm())
+        })
+    }
+
+    pub(crate) fn get(&self, name: &str) -> Option<KeyMaterial> {
+        self.keys.lock(|keys| {
+            keys.borrow()
+                .iter()
+                .find(|k| k.name == name)
+                .map(|k| k.material.clone())
+        })
+    }
+
+    /// Encodes the persistent keys.
+    ///
+    /// Every key is the length of its name (`u8`), the name, the identifier of its algorithm,
+    /// whether it is a public key (`u8`), the length of the material (`u8`) and the material.
+    fn encode(&self) -> Zeroizing<Vec<u8>> {
+        self.keys.lock(|keys| {
+            let mut out = Zeroizing::new(Vec::new());
+            for key in keys.borrow().iter().filter(|k| k.persistent) {
+                let material = key.material.to_bytes();
+                out.push(key.name.len() as u8);
+                out.extend_from_slice(key.name.as_bytes());
+                out.push(algorithm_id(key.material.algorithm()));
+                out.push(u8::from(matches!(key.material, KeyMaterial::Ed25519(_))));
+                out.push(material.len() as u8);
+                out.extend_from_slice(&material);
+            }
+            out
+        })
+    }
+
+    fn decode(&self, mut data: &[u8]) -> Result<(), KeyStoreError> {
+        fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], KeyStoreError> {
+            let (head, tail) = data.split_at_checked(len).ok_or(KeyStoreError::Corrupted)?;
+            *data = tail;
+            Ok(head)
+        }
+
+        while !data.is_empty() {
+            let name_len = take(&mut data, 1)?[0];
+            let name = core::str::from_utf8(take(&mut data, name_len.into())?)
+                .map_err(|_| KeyStoreError::Corrupted)?;
+            let header = take(&mut data, 3)?;
+            let algorithm = algorithm_from_id(header[0]).ok_or(KeyStoreError::Corrupted)?;
+            let bytes = take(&mut data, header[2].into())?;
+            let material = if header[1] != 0 {
+                KeyMaterial::from_bytes(algorithm, bytes)
+            } else {
+                KeyMaterial::from_secret_bytes(algorithm, bytes)
+            }
+            .map_err(|_| KeyStoreError::Corrupted)?;
+            self.insert(name, material, true)?;
+        }
+        Ok(())
+    }
+
+    /// Restores the keys saved to flash, to be called once at startup.
+    pub async fn load(&self) -> Result<(), KeyStoreError> {
+        let data = ariel_os_storage::get::<Vec<u8>>(STORAGE_KEY)
+            .await
+            .map_err(|_| KeyStoreError::Storage)?
+            .map(Zeroizing::new);
+        match data {
+            Some(data) => self.decode(&data),
+            None => Ok(()),
+        }
+    }
+
+    /// Saves the persistent keys to flash whenever they change.
+    ///
+    /// A failed write is retried after [`RETRY_DELAY`], with the keys as they are then.
+    pub async fn run_persistence(&self) -> ! {
+        loop {
+            self.dirty.wait().await;
+            let data = self.encode();
+            if ariel_os_storage::insert(STORAGE_KEY, &data[..])
+                .await
+                .is_err()
+            {
+                ariel_os_debug::log::warn!("Saving the keys failed, retrying");
+                Timer::after(RETRY_DELAY).await;
+                self.dirty.signal(());
+            }
+        }
+    }
+}
+
+impl Default for KeyStore {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ArielOSHost {
+    /// Lets the capsule open the keys of `store` named in `names`.
+    pub fn bind_key_store(&mut self, store: &'static KeyStore, names: &[&str]) {
+        self.crypto_host.key_store = Some(store);
+        self.crypto_host.granted_keys = names.iter().map(|name| String::from(*name)).collect();
+    }
+}
@@ -0,0 +1,99 @@
+use core::ops::{Deref, DerefMut};
+use core::pin::Pin;
+use core::task::{Context, Poll};
+
+use ariel_os_embassy::reexports::embassy_net::Stack;
+#[cfg(feature = "tcp")]
+use ariel_os_embassy::reexports::embassy_net::tcp::TcpSocket;
+#[cfg(feature = "udp")]
+use ariel_os_embassy::reexports::embassy_net::udp::UdpSocket;
+
+/// The network stack, or one of its sockets, held by the [`ArielOSHost`].
+///
+/// wasmtime requires the host state of a store, and the futures of its async host functions, to
+/// be `Send`. The stack and the sockets of embassy-net are not, as they share a `RefCell`. They
+/// are however only ever used from the executor running the network stack: the functions
+/// handing them to the host are `unsafe` and require the host to stay on that executor.
+///
+/// [`ArielOSHost`]: super::ArielOSHost
+#[derive(Clone, Copy)]
+pub(crate) struct Local<T: NetworkValue>(T);
+
+mod sealed {
+    pub trait Sealed {}
+}
+
+/// Types of embassy-net [`Local`] may hold.
+pub(crate) trait NetworkValue: sealed::Sealed {}
+
+// For any lifetime, as the compiler erases them when checking whether futures are `Send`
+macro_rules! network_values {
+    ($($(#[$attr:meta])* $t:ident),* $(,)?) => {
+        $(
+            $(#[$attr])*
+            impl sealed::Sealed for $t<'_> {}
+            $(#[$attr])*
+            impl NetworkValue for $t<'_> {}
+        )*
+    };
+}
+
+network_values!(
+    Stack,
+    #[cfg(feature = "udp")]
+    UdpSocket,
+    #[cfg(feature = "tcp")]
+    TcpSocket,
+);
+
+// SAFETY: The value is only accessed from the executor of the network stack, which `new`
+// requires
+unsafe impl<T: NetworkValue> Send for Local<T> {}
+
+impl<T: NetworkValue> Local<T> {
+    /// # Safety
+    ///
+    /// The host holding the value must only be used on the executor running the network stack.
+    pub(crate) unsafe fn new(value: T) -> Self {
+        Self(value)
+    }
+
+    /// Starts an operation of the value, whose future is then as local as the value.
+    ///
+    /// The operation may only capture values that are `Send` themselves.
+    pub(crate) fn run<'a, F: Future>(
+        &'a mut self,
+        operation: impl FnOnce(&'a mut T) -> F + Send,
+    ) -> LocalFuture<F> {
+        LocalFuture(operation(&mut self.0))
+    }
+}
+
+impl<T: NetworkValue> Deref for Local<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.0
+    }
+}
+
+impl<T: NetworkValue> DerefMut for Local<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.0
+    }
+}
+
+/// A future of a [`Local`] value, see [`Local::run`].
+pub(crate) struct LocalFuture<F>(F);
+
+// SAFETY: Only built from a `Local` value and `Send` captures, it is polled where the value lives
+unsafe impl<F> Send for LocalFuture<F> {}
+
+impl<F: Future> Future for LocalFuture<F> {
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<F::Output> {
+        // SAFETY: The future is structurally pinned, it is never moved out of `self`
+        unsafe { self.map_unchecked_mut(|local| &mut local.0) }.poll(cx)
+    }
+}
@@ -1,7 +1,21 @@
-use ariel_os_debug::log::info;
+use ariel_os_debug::log::{debug, error, info, trace, warn};
 
 extern crate alloc;
 use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::fmt::Write as _;
+
+use ariel_os_guest_log::tag;
+
+#[cfg(feature = "log-buffer")]
+use alloc::collections::VecDeque;
+#[cfg(feature = "log-buffer")]
+use ariel_os_embassy::api::time::Instant;
+#[cfg(feature = "log-buffer")]
+use core::cell::RefCell;
+#[cfg(feature = "log-buffer")]
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
 
 use wasmtime::component::bindgen;
 
@@ -12,10 +26,375 @@ bindgen!({
     path: "../../wit/",
 });
 
-pub use ariel::wasm_bindings::log_api::{Host, HostWithStore, add_to_linker};
+pub use ariel::wasm_bindings::log_api::{Field, Host, HostWithStore, Level, add_to_linker};
+
+/// Prefix of the entries of capsules that weren't given a name.
+const DEFAULT_NAME: &str = "WASM";
+
+fn severity(level: Level) -> u8 {
+    match level {
+        Level::Trace => 0,
+        Level::Debug => 1,
+        Level::Info => 2,
+        Level::Warn => 3,
+        Level::Error => 4,
+    }
+}
+
+/// Format strings of the deferred log entries of a capsule.
+///
+/// This is the table `precompile_wasm.rs` extracts from the payload next to the precompiled
+/// output, see the `ariel-os-guest-log` crate for its layout.
+#[derive(Clone, Copy)]
+pub struct FormatTable {
+    data: &'static [u8],
+}
+
+impl FormatTable {
+    pub const fn new(data: &'static [u8]) -> Self {
+        Self { data }
+    }
+
+    fn get(&self, id: u32) -> Option<&'static str> {
+        ariel_os_guest_log::entries(self.data)
+            .find(|(entry_id, _)| *entry_id == id)
+            .map(|(_, format)| format)
+    }
+}
+
+/// Decodes the next argument of a deferred entry and writes it to `out`.
+///
+/// Returns the remaining arguments, or `None` if they are malformed.
+fn render_arg<'a>(args: &'a [u8], out: &mut String) -> Option<&'a [u8]> {
+    let (&kind, args) = args.split_first()?;
+    let size = match kind {
+        tag::BOOL => 1,
+        tag::U32 | tag::I32 | tag::F32 => 4,
+        tag::U64 | tag::I64 | tag::F64 => 8,
+        tag::STR => 2 + usize::from(u16::from_le_bytes(args.get(..2)?.try_into().ok()?)),
+        _ => return None,
+    };
+    let value = args.get(..size)?;
+
+    // Writing to a String can't fail
+    let _ = match kind {
+        tag::BOOL => write!(out, "{}", value[0] != 0),
+        tag::U32 => write!(out, "{}", u32::from_le_bytes(value.try_into().ok()?)),
+        tag::I32 => write!(out, "{}", i32::from_le_bytes(value.try_into().ok()?)),
+        tag::F32 => write!(out, "{}", f32::from_le_bytes(value.try_into().ok()?)),
+        tag::U64 => write!(out, "{}", u64::from_le_bytes(value.try_into().ok()?)),
+        tag::I64 => write!(out, "{}", i64::from_le_bytes(value.try_into().ok()?)),
+        tag::F64 => write!(out, "{}", f64::from_le_bytes(value.try_into().ok()?)),
+        _ => {
+            out.push_str(core::str::from_utf8(&value[2..]).ok()?);
+            Ok(())
+        }
+    };
+    Some(&args[size..])
+}
+
+/// Renders `format` by replacing each `{}` with the next argument.
+///
+/// Missing or malformed arguments are rendered as `?`.
+fn render(format: &str, mut args: &[u8]) -> String {
+    let mut out = String::with_capacity(format.len());
+    let mut chars = format.chars().peekable();
+    while let Some(c) = chars.next() {
+        match (c, chars.peek()) {
+            ('{', Some('{')) | ('}', Some('}')) => {
+                chars.next();
+                out.push(c);
+            }
+            ('{', Some('}')) => {
+                chars.next();
+                match render_arg(args, &mut out) {
+                    Some(rest) => args = rest,
+                    None => {
+                        args = &[];
+                        out.push('?');
+                    }
+                }
+            }
+            _ => out.push(c),
+        }
+    }
+    out
+}
+
+/// Longest line kept in a [`LogRing`], longer lines are truncated.
+#[cfg(feature = "log-buffer")]
+pub const MAX_BUFFERED_LINE_LEN: usize = 128;
+
+#[cfg(feature = "log-buffer")]
+#[derive(Clone)]
+struct BufferedEntry {
+    timestamp_ms: u64,
+    level: Level,
+    line: String,
+}
+
+#[cfg(feature = "log-buffer")]
+struct RingInner {
+    entries: VecDeque<BufferedEntry>,
+    /// Number of entries ever pushed, used to tell apart successive contents.
+    sequence: u32,
+}
+
+/// Bounded buffer of the most recent log entries of a capsule.
+///
+/// The ring is shared between the log host of the capsule, which pushes to it, and a
+/// [`LogHandler`](super::coap::LogHandler) that serves it over CoAP.
+#[cfg(feature = "log-buffer")]
+pub struct LogRing {
+    capacity: usize,
+    inner: Mutex<CriticalSectionRawMutex, RefCell<RingInner>>,
+}
+
+#[cfg(feature = "log-buffer")]
+impl LogRing {
+    /// Creates a ring that keeps the `capacity` most recent entries.
+    pub const fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            inner: Mutex::new(RefCell::new(RingInner {
+                entries: VecDeque::new(),
+                sequence: 0,
+            })),
+        }
+    }
+
+    fn push(&self, level: Level, line: &str) {
+        if self.capacity == 0 {
+            return;
+        }
+        let mut end = line.len().min(MAX_BUFFERED_LINE_LEN);
+        while !line.is_char_boundary(end) {
+            end -= 1;
+        }
+        let entry = BufferedEntry {
+            timestamp_ms: Instant::now().as_millis(),
+            level,
+            line: String::from(&line[..end]),
+        };
+        self.inner.lock(|inner| {
+            let mut inner = inner.borrow_mut();
+            if inner.entries.len() >= self.capacity {
+                inner.entries.pop_front();
+            }
+            inner.entries.push_back(entry);
+            inner.sequence = inner.sequence.wrapping_add(1);
+        });
+    }
+
+    /// Number of entries ever pushed to the ring (wrapping).
+    pub fn sequence(&self) -> u32 {
+        self.inner.lock(|inner| inner.borrow().sequence)
+    }
+
+    /// Writes the buffered entries, oldest first, one per line.
+    ///
+    /// The entries are copied out first, so the lock isn't held while `w` formats them.
+    pub fn write_to<W: core::fmt::Write>(&self, w: &mut W) -> core::fmt::Result {
+        let entries: Vec<BufferedEntry> = self
+            .inner
+            .lock(|inner| inner.borrow().entries.iter().cloned().collect());
+        for entry in entries {
+            let level = match entry.level {
+                Level::Trace => "TRACE",
+                Level::Debug => "DEBUG",
+                Level::Info => "INFO",
+                Level::Warn => "WARN",
+                Level::Error => "ERROR",
+            };
+            writeln!(w, "[{}] {} {}", entry.timestamp_ms, level, entry.line)?;
+        }
+        Ok(())
+    }
+}
+
+pub(crate) struct ArielLogHost {
+    pub(crate) name: Option<String>,
+    pub(crate) min_level: Level,
+    pub(crate) formats: Option<FormatTable>,
+    #[cfg(feature = "log-buffer")]
+    pub(crate) ring: Option<&'static LogRing>,
+}
+
+impl Default for ArielLogHost {
+    fn default() -> Self {
+        Self {
+            name: None,
+            min_level: Level::Trace,
+            formats: None,
+            #[cfg(feature = "log-buffer")]
+            ring: None,
+        }
+    }
+}
+
+impl ArielLogHost {
+    fn name(&self) -> &str {
+        self.name.as_deref().unwrap_or(DEFAULT_NAME)
+    }
+
+    fn emit(&self, level: Level, line: &str) {
+        #[cfg(feature = "log-buffer")]
+        if let Some(ring) = self.ring {
+            ring.push(level, line);
+        }
+
+        let name = self.name();
+        match level {
+            Level::Trace => trace!("[{}] {}", name, line),
+            Level::Debug => debug!("[{}] {}", name, line),
+            Level::Info => info!("[{}] {}", name, line),
+            Level::Warn => warn!("[{}] {}", name, line),
+            Level::Error => error!("[{}] {}", name, line),
+        }
+    }
+}
+
+impl Host for ArielLogHost {
+    fn info(&mut self, input: String) {
+        self.log(Level::Info, input, Vec::new());
+    }
+
+    fn log(&mut self, level: Level, message: String, fields: Vec<Field>) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        let mut line = message;
+        for field in fields {
+            // Writing to a String can't fail
+            let _ = write!(line, " {}={}", field.key, field.value);
+        }
+        self.emit(level, &line);
+    }
+
+    fn log_deferred(&mut self, level: Level, format_id: u32, args: Vec<u8>) {
+        if !self.enabled(level) {
+            return;
+        }
+
+        match self.formats.and_then(|f| f.get(format_id)) {
+            Some(format) => self.emit(level, &render(format, &args)),
+            None => {
+                let mut line = String::new();
+                let _ = write!(line, "<unknown format {:#010x}> {:?}", format_id, args);
+                self.emit(level, &line);
+            }
+        }
+    }
+
+    fn enabled(&mut self, level: Level) -> bool {
+        severity(level) >= severity(self.min_level)
+    }
+}
 
 impl Host for ArielOSHost {
     fn info(&mut self, input: String) {
-        info!("[WASM] {}", input.as_str());
+        self.log_host.info(input)
+    }
+
+    fn log(&mut self, level: Level, message: String, fields: Vec<Field>) {
+        self.log_host.log(level, message, fields)
+    }
+
+    fn log_deferred(&mut self, level: Level, format_id: u32, args: Vec<u8>) {
+        self.log_host.log_deferred(level, format_id, args)
+    }
+
+    fn enabled(&mut self, level: Level) -> bool {
+        self.log_host.enabled(level)
+    }
+}
+
+impl ArielOSHost {
+    /// Prefixes the log entries of the capsule with `name` instead of `WASM`.
+    pub fn set_log_name(&mut self, name: &str) {
+        self.log_host.name = Some(String::from(name));
+    }
+
+    /// Drops the log entries of the capsule that are below `level`.
+    ///
+    /// This can be changed while the capsule runs through [`wasmtime::Store::data_mut`].
+    pub fn set_log_min_level(&mut self, level: Level) {
+        self.log_host.min_level = level;
+    }
+
+    /// Provides the format strings of the deferred log entries of the capsule.
+    ///
+    /// `table` is the `.logfmt` file written by `precompile_wasm.rs` next to the payload.
+    pub fn set_log_formats(&mut self, table: &'static [u8]) {
+        self.log_host.formats = Some(FormatTable::new(table));
+    }
+
+    /// Additionally keeps the recent log entries of the capsule in `ring`.
+    #[cfg(feature = "log-buffer")]
+    pub fn set_log_ring(&mut self, ring: &'static LogRing) {
+        self.log_host.ring = Some(ring);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ariel_os_guest_log::{Arg, Encoder, entry, entry_len, format_id};
+
+    fn args(values: &[&dyn Arg]) -> Vec<u8> {
+        let mut encoder = Encoder::new();
+        for value in values {
+            value.encode(&mut encoder);
+        }
+        encoder.as_bytes().to_vec()
+    }
+
+    #[test]
+    fn renders_arguments() {
+        let args = args(&[&42u16, &-7i32, &u64::MAX, &-1i64, &true, &"abc", &0.5f32]);
+        assert_eq!(
+            render("{} {} {} {} {} {} {}", &args),
+            "42 -7 18446744073709551615 -1 true abc 0.5"
+        );
+    }
+
+    #[test]
+    fn renders_escapes() {
+        assert_eq!(render("{{}} {}{{", &args(&[&1u8])), "{} 1{");
+        assert_eq!(render("no placeholder", &[]), "no placeholder");
+    }
+
+    #[test]
+    fn renders_missing_arguments() {
+        assert_eq!(render("{} and {}", &args(&[&1u8])), "1 and ?");
+        // Extra arguments are ignored
+        assert_eq!(render("{}", &args(&[&1u8, &2u8])), "1");
+    }
+
+    #[test]
+    fn renders_malformed_arguments() {
+        // Unknown tag
+        assert_eq!(render("{} {}", &[0xff, 1, 2]), "? ?");
+        // Truncated value
+        assert_eq!(render("{}", &[tag::U32, 1, 2]), "?");
+        // String longer than the arguments
+        assert_eq!(render("<{}>", &[tag::STR, 5, 0, b'a']), "<?>");
+        // Invalid UTF-8
+        assert_eq!(render("{}", &[tag::STR, 1, 0, 0xff]), "?");
+    }
+
+    #[test]
+    fn looks_up_formats() {
+        const FIRST: &str = "first {}";
+        const SECOND: &str = "second";
+        let mut data = entry::<{ entry_len(FIRST) }>(FIRST).to_vec();
+        data.extend_from_slice(&entry::<{ entry_len(SECOND) }>(SECOND));
+        let table = FormatTable::new(data.leak());
+
+        assert_eq!(table.get(format_id(FIRST)), Some(FIRST));
+        assert_eq!(table.get(format_id(SECOND)), Some(SECOND));
+        assert_eq!(table.get(format_id("third")), None);
     }
 }
@@ -4,12 +4,52 @@ pub mod log;
 #[cfg(feature = "rng")]
 pub mod rng;
 
+#[cfg(feature = "csprng")]
+pub mod csprng;
+
 #[cfg(feature = "time")]
 pub mod time;
 
 #[cfg(feature = "udp")]
 pub mod udp;
 
+#[cfg(feature = "tcp")]
+pub mod tcp;
+
+#[cfg(feature = "dns")]
+pub mod dns;
+
+#[cfg(feature = "dtls")]
+pub mod dtls;
+
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+
+#[cfg(feature = "http-client")]
+pub mod http_client;
+
+#[cfg(feature = "net-info")]
+pub mod net_info;
+
+#[cfg(any(
+    feature = "udp",
+    feature = "tcp",
+    feature = "dns",
+    feature = "net-info"
+))]
+pub mod ip_types;
+
+#[cfg(any(feature = "udp", feature = "tcp"))]
+pub mod net_policy;
+
+#[cfg(any(
+    feature = "udp",
+    feature = "tcp",
+    feature = "dns",
+    feature = "net-info"
+))]
+mod local;
+
 #[cfg(feature = "coap")]
 pub mod coap;
 
@@ -19,14 +59,182 @@ pub mod gpio;
 #[cfg(feature = "sensors")]
 pub mod sensors;
 
+#[cfg(feature = "i2c")]
+pub mod i2c;
+
+#[cfg(feature = "spi")]
+pub mod spi;
+
+#[cfg(feature = "uart")]
+pub mod uart;
+
+#[cfg(feature = "pwm")]
+pub mod pwm;
+
+#[cfg(feature = "adc")]
+pub mod adc;
+
+#[cfg(feature = "wall-clock")]
+pub mod wall_clock;
+
+#[cfg(feature = "crypto")]
+pub mod crypto;
+
+#[cfg(feature = "keystore")]
+pub mod keystore;
+
 #[derive(Default)]
 pub struct ArielOSHost {
+    #[cfg(feature = "log")]
+    log_host: crate::wasm::log::ArielLogHost,
+
     #[cfg(feature = "rng")]
     rng_host: crate::wasm::rng::ArielRNGHost,
 
+    #[cfg(feature = "csprng")]
+    csprng_host: crate::wasm::csprng::ArielCsprngHost,
+
+    #[cfg(feature = "time")]
+    time_host: crate::wasm::time::ArielTimeHost,
+
     #[cfg(feature = "udp")]
     udp_host: crate::wasm::udp::ArielUDPHost,
 
+    #[cfg(feature = "tcp")]
+    tcp_host: crate::wasm::tcp::ArielTcpHost,
+
+    #[cfg(feature = "dns")]
+    dns_host: crate::wasm::dns::ArielDnsHost,
+
+    #[cfg(feature = "dtls")]
+    dtls_host: crate::wasm::dtls::ArielDtlsHost,
+
+    #[cfg(feature = "mqtt")]
+    mqtt_host: crate::wasm::mqtt::ArielMqttHost,
+
+    #[cfg(feature = "http-client")]
+    http_client_host: crate::wasm::http_client::ArielHttpClientHost,
+
+    #[cfg(feature = "net-info")]
+    net_info_host: crate::wasm::net_info::ArielNetInfoHost,
+
     #[cfg(feature = "gpio")]
     gpio_host: crate::wasm::gpio::ArielGpioHost,
+
+    #[cfg(feature = "i2c")]
+    i2c_host: crate::wasm::i2c::ArielI2cHost,
+
+    #[cfg(feature = "spi")]
+    spi_host: crate::wasm::spi::ArielSpiHost,
+
+    #[cfg(feature = "uart")]
+    uart_host: crate::wasm::uart::ArielUartHost,
+
+    #[cfg(feature = "pwm")]
+    pwm_host: crate::wasm::pwm::ArielPwmHost,
+
+    #[cfg(feature = "adc")]
+    adc_host: crate::wasm::adc::ArielAdcHost,
+
+    #[cfg(feature = "wall-clock")]
+    wall_clock_host: crate::wasm::wall_clock::ArielWallClockHost,
+
+    #[cfg(feature = "crypto")]
+    crypto_host: crate::wasm::crypto::ArielCryptoHost,
+}
+
+impl ArielOSHost {
+    /// Runs `wait`, an awaited host call, until it completes or the deadline the capsule set
+    /// through `time-api` passes, in which case `expired` is returned instead.
+    #[cfg(any(
+        feature = "gpio",
+        feature = "sensors-async",
+        feature = "i2c",
+        feature = "spi",
+        feature = "uart",
+        feature = "udp",
+        feature = "tcp",
+        feature = "dns",
+        feature = "net-info",
+        feature = "dtls",
+        feature = "mqtt",
+        feature = "http-client",
+    ))]
+    pub(crate) async fn before_deadline<'a, T, F: Future<Output = T>>(
+        &'a mut self,
+        wait: impl FnOnce(&'a mut Self) -> F,
+        expired: T,
+    ) -> T {
+        #[cfg(feature = "time")]
+        {
+            let deadline = self.time_host.deadline;
+            time::before_deadline(deadline, wait(self), expired).await
+        }
+
+        #[cfg(not(feature = "time"))]
+        {
+            let _ = expired;
+            wait(self).await
+        }
+    }
+
+    /// Makes the runs of the capsule reproducible.
+    ///
+    /// `rng-api` becomes a deterministic PRNG seeded with `seed` and `time-api` switches to
+    /// virtual time starting at zero, so the same inputs produce the same behaviour.
+    #[cfg(any(feature = "rng", feature = "time"))]
+    #[cfg_attr(not(feature = "rng"), allow(unused_variables))]
+    pub fn set_deterministic(&mut self, seed: u64) {
+        #[cfg(feature = "rng")]
+        self.seed_rng(seed);
+        #[cfg(feature = "time")]
+        self.use_virtual_time(0);
+    }
+}
+
+#[cfg(all(test, feature = "rng", feature = "time"))]
+mod tests {
+    use super::*;
+
+    extern crate alloc;
+    use alloc::vec::Vec;
+
+    use embassy_futures::block_on;
+    use rng::HostRNG as _;
+    use time::{Host as _, HostTimer as _};
+
+    /// Drives a capsule-like sequence of calls and records what the capsule observes.
+    fn run(seed: u64) -> (Vec<u64>, Vec<u8>) {
+        let mut host = ArielOSHost::default();
+        host.set_deterministic(seed);
+
+        let mut observed = Vec::new();
+        let bytes = host.random_bytes(13);
+        block_on(async {
+            observed.push(host.now_as_micros());
+            for _ in 0..3 {
+                let millis = host.next_u32() % 100;
+                host.sleep(u64::from(millis)).await;
+                observed.push(host.now_as_micros());
+            }
+            let timer = host.every(2_500).unwrap();
+            for _ in 0..2 {
+                host.wait(wasmtime::component::Resource::new_own(timer.rep()))
+                    .await;
+                observed.push(host.now_as_micros());
+            }
+            observed.push(host.next_u64());
+        });
+        (observed, bytes)
+    }
+
+    #[test]
+    fn same_seed_same_run() {
+        let first = run(7);
+        assert_eq!(first, run(7));
+        assert_ne!(first, run(8));
+        // Virtual time starts at zero and only moves when the capsule waits
+        assert_eq!(first.0[0], 0);
+        assert_eq!(first.0[4] + 2_500, first.0[5]);
+    }
 }
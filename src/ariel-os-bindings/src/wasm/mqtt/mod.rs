@@ -0,0 +1,613 @@
+mod packet;
+
+use ariel_os_embassy::api::time::{Duration, Instant, Timer};
+use ariel_os_embassy::reexports::embassy_net::IpEndpoint;
+use ariel_os_embassy::reexports::embassy_net::tcp::TcpSocket;
+
+use embassy_futures::select::{Either, select};
+
+use wasmtime::component::{Resource, bindgen};
+
+extern crate alloc;
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::ArielOSHost;
+use super::ip_types::{Endpoint, UnsupportedAddress};
+use super::local::Local;
+use super::net_policy::PolicyError;
+use super::tcp::{ArielTcpHost, TcpError};
+
+use packet::{ConnectFields, Packet};
+
+bindgen!({
+    world: "ariel:wasm-bindings/mqtt",
+    path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+    },
+    imports: {
+        "ariel:wasm-bindings/mqtt-api.[static]mqtt-client.connect": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.publish": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.subscribe": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.unsubscribe": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.next-message": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.try-next-message": async,
+        "ariel:wasm-bindings/mqtt-api.[method]mqtt-client.disconnect": async,
+    }
+});
+
+pub use ariel::wasm_bindings::mqtt_api::{
+    self as gen_mqtt, ConnectOptions, Host, HostMqttClient, HostWithStore, Message, MqttClient,
+    MqttError, Qos, add_to_linker,
+};
+
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Messages queued per connection until the capsule reads them.
+const MAX_INBOX_LEN: usize = 16;
+
+impl From<PolicyError> for MqttError {
+    fn from(value: PolicyError) -> Self {
+        match value {
+            PolicyError::Denied => MqttError::PermissionDenied,
+            PolicyError::RateLimited => MqttError::RateLimited,
+        }
+    }
+}
+
+impl From<UnsupportedAddress> for MqttError {
+    fn from(_: UnsupportedAddress) -> Self {
+        MqttError::UnsupportedAddress
+    }
+}
+
+impl From<TcpError> for MqttError {
+    fn from(value: TcpError) -> Self {
+        match value {
+            TcpError::NoBuffers => MqttError::NoBuffers,
+            _ => MqttError::ConnectionFailed,
+        }
+    }
+}
+
+fn qos_level(qos: Qos) -> u8 {
+    match qos {
+        Qos::AtMostOnce => 0,
+        Qos::AtLeastOnce => 1,
+    }
+}
+
+/// Checks a topic name, or a topic filter when `filter` is set.
+fn check_topic(topic: &str, filter: bool) -> Result<(), MqttError> {
+    let valid = !topic.is_empty()
+        && topic.len() <= usize::from(u16::MAX)
+        && !topic.contains('\0')
+        && (filter || !topic.contains(['+', '#']));
+    valid.then_some(()).ok_or(MqttError::InvalidTopic)
+}
+
+/// A connection to a broker.
+struct Connection {
+    socket: Local<TcpSocket<'static>>,
+    /// Received bytes not forming a complete packet yet.
+    rx: Vec<u8>,
+    /// Messages received while waiting for something else.
+    inbox: VecDeque<Message>,
+    last_packet_id: u16,
+    keep_alive: Option<Duration>,
+    last_sent: Instant,
+    /// When the PINGREQ still waiting for its PINGRESP was sent.
+    ping_sent: Option<Instant>,
+    /// Set while a packet is written, to notice a write cut short by the deadline of the capsule.
+    sending: bool,
+    closed: bool,
+}
+
+impl Connection {
+    fn next_packet_id(&mut self) -> u16 {
+        // Zero is not a valid packet identifier
+        self.last_packet_id = self.last_packet_id.checked_add(1).unwrap_or(1);
+        self.last_packet_id
+    }
+
+    /// Marks the connection as closed if `result` means it is unusable.
+    fn check<T>(&mut self, result: Result<T, MqttError>) -> Result<T, MqttError> {
+        if let Err(MqttError::Disconnected | MqttError::ProtocolError | MqttError::PacketTooLarge) =
+            result
+        {
+            self.closed = true;
+            self.socket.abort();
+        }
+        result
+    }
+
+    async fn send(&mut self, mut packet: &[u8]) -> Result<(), MqttError> {
+        if self.closed {
+            return Err(MqttError::Disconnected);
+        }
+        if self.sending {
+            // The broker would read the next packet as the rest of the previous one
+            return self.check(Err(MqttError::Disconnected));
+        }
+        self.sending = true;
+        while !packet.is_empty() {
+            match self.socket.run(|socket| socket.write(packet)).await {
+                Ok(n) if n > 0 => packet = &packet[n..],
+                _ => return self.check(Err(MqttError::Disconnected)),
+            }
+        }
+        self.sending = false;
+        self.last_sent = Instant::now();
+        Ok(())
+    }
+
+    /// Reads what the socket has received, waiting for at least one byte.
+    async fn fill(&mut self) -> Result<(), MqttError> {
+        let mut chunk = [0; 256];
+        match self.socket.run(|socket| socket.read(&mut chunk)).await {
+            Ok(n) if n > 0 => {
+                self.rx.extend_from_slice(&chunk[..n]);
+                Ok(())
+            }
+            // Zero bytes mean the broker closed the connection
+            _ => self.check(Err(MqttError::Disconnected)),
+        }
+    }
+
+    fn take_packet(&mut self) -> Result<Option<Packet>, MqttError> {
+        let decoded = packet::decode(&self.rx);
+        match self.check(decoded)? {
+            Some((packet, len)) => {
+                self.rx.drain(..len);
+                Ok(Some(packet))
+            }
+            None => Ok(None),
+        }
+    }
+
+    async fn read_packet(&mut self) -> Result<Packet, MqttError> {
+        loop {
+            if let Some(packet) = self.take_packet()? {
+                return Ok(packet);
+            }
+            self.fill().await?;
+        }
+    }
+
+    /// Queues received messages, returning any other packet.
+    async fn dispatch(&mut self, packet: Packet) -> Result<Option<Packet>, MqttError> {
+        match packet {
+            Packet::Publish {
+                topic,
+                payload,
+                qos,
+                retain,
+                packet_id,
+            } => {
+                if self.inbox.len() >= MAX_INBOX_LEN {
+                    // Not acknowledged either, so that the broker delivers it again to a
+                    // persistent session
+                    return Ok(None);
+                }
+                if let Some(packet_id) = packet_id {
+                    self.send(&packet::puback(packet_id)).await?;
+                }
+                self.inbox.push_back(Message {
+                    topic,
+                    payload,
+                    qos: if qos == 0 {
+                        Qos::AtMostOnce
+                    } else {
+                        Qos::AtLeastOnce
+                    },
+                    retain,
+                });
+                Ok(None)
+            }
+            Packet::PingResp => {
+                self.ping_sent = None;
+                Ok(None)
+            }
+            other => Ok(Some(other)),
+        }
+    }
+
+    /// Waits until the broker sends a packet `accept` returns a value for.
+    ///
+    /// Messages arriving in the meantime are queued, other packets are ignored.
+    async fn wait_for<T>(
+        &mut self,
+        timeout: Duration,
+        accept: impl Fn(&Packet) -> Option<T>,
+    ) -> Result<T, MqttError> {
+        let deadline = Instant::now() + timeout;
+        loop {
+            let packet = match select(self.read_packet(), Timer::at(deadline)).await {
+                Either::First(packet) => packet?,
+                Either::Second(()) => return Err(MqttError::TimedOut),
+            };
+            if let Some(packet) = self.dispatch(packet).await?
+                && let Some(value) = accept(&packet)
+            {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+/// MQTT clients of a capsule.
+///
+/// Connections use the sockets of the TCP host and are subject to its policy. The
+/// representation of a [`MqttClient`] resource is the index of the connection in
+/// `connections`.
+pub(crate) struct ArielMqttHost {
+    connections: Vec<Option<Connection>>,
+    /// Connection being established.
+    ///
+    /// It stays here until the broker accepted it, so a `connect` cut short by the deadline of
+    /// the capsule doesn't lose the socket: the next `connect` returns it to the TCP host.
+    connecting: Option<Connection>,
+    timeout: Duration,
+}
+
+impl Default for ArielMqttHost {
+    fn default() -> Self {
+        Self {
+            connections: Vec::new(),
+            connecting: None,
+            timeout: DEFAULT_TIMEOUT,
+        }
+    }
+}
+
+impl ArielMqttHost {
+    fn connection(&mut self, handle: &Resource<MqttClient>) -> &mut Connection {
+        // Handles only come from `connect` and are removed on drop
+        self.connections[handle.rep() as usize]
+            .as_mut()
+            .expect("Client used after being dropped")
+    }
+
+    async fn connect(
+        &mut self,
+        tcp: &mut ArielTcpHost,
+        broker: Endpoint,
+        options: ConnectOptions,
+    ) -> Result<Resource<MqttClient>, MqttError> {
+        if let Some(connection) = self.connecting.take() {
+            tcp.release(connection.socket);
+        }
+        let broker = IpEndpoint::try_from(broker)?;
+        tcp.policy.check_remote(&broker)?;
+        let connect = packet::connect(&ConnectFields {
+            client_id: &options.client_id,
+            keep_alive_secs: options.keep_alive_secs,
+            clean_session: options.clean_session,
+            username: options.username.as_deref(),
+            password: options.password.as_deref(),
+        })?;
+
+        let connection = self.connecting.insert(Connection {
+            socket: tcp.lease()?,
+            rx: Vec::new(),
+            inbox: VecDeque::new(),
+            last_packet_id: 0,
+            keep_alive: (options.keep_alive_secs > 0)
+                .then(|| Duration::from_secs(u64::from(options.keep_alive_secs))),
+            last_sent: Instant::now(),
+            ping_sent: None,
+            sending: false,
+            closed: false,
+        });
+
+        let timeout = Timer::after(self.timeout);
+        let connected = select(
+            connection.socket.run(|socket| socket.connect(broker)),
+            timeout,
+        );
+        let result = match connected.await {
+            Either::First(Ok(())) => Ok(()),
+            Either::First(Err(_)) => Err(MqttError::ConnectionFailed),
+            Either::Second(()) => Err(MqttError::TimedOut),
+        };
+        let result = match result {
+            Ok(()) => connection.send(&connect).await,
+            Err(err) => Err(err),
+        };
+        let result = match result {
+            Ok(()) => {
+                connection
+                    .wait_for(self.timeout, |packet| match packet {
+                        Packet::ConnAck { return_code } => Some(*return_code),
+                        _ => None,
+                    })
+                    .await
+            }
+            Err(err) => Err(err),
+        };
+        let error = match result {
+            Ok(0) => None,
+            Ok(4 | 5) => Some(MqttError::NotAuthorized),
+            Ok(_) => Some(MqttError::Refused),
+            Err(err) => Some(err),
+        };
+        let connection = self
+            .connecting
+            .take()
+            .expect("The connection is being established");
+        if let Some(err) = error {
+            tcp.release(connection.socket);
+            return Err(err);
+        }
+
+        let index = match self.connections.iter().position(Option::is_none) {
+            Some(index) => {
+                self.connections[index] = Some(connection);
+                index
+            }
+            None => {
+                self.connections.push(Some(connection));
+                self.connections.len() - 1
+            }
+        };
+        Ok(Resource::new_own(index as u32))
+    }
+
+    async fn publish(
+        &mut self,
+        tcp: &mut ArielTcpHost,
+        self_: Resource<MqttClient>,
+        topic: String,
+        payload: Vec<u8>,
+        qos: Qos,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        check_topic(&topic, false)?;
+        tcp.policy.consume(payload.len())?;
+        let timeout = self.timeout;
+        let connection = self.connection(&self_);
+        let qos = qos_level(qos);
+        let packet_id = (qos > 0).then(|| connection.next_packet_id());
+        let packet = packet::publish(&topic, &payload, qos, retain, packet_id)?;
+        connection.send(&packet).await?;
+        if let Some(packet_id) = packet_id {
+            connection
+                .wait_for(timeout, |packet| {
+                    matches!(packet, Packet::PubAck(id) if *id == packet_id).then_some(())
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    async fn subscribe(
+        &mut self,
+        self_: Resource<MqttClient>,
+        filter: String,
+        qos: Qos,
+    ) -> Result<Qos, MqttError> {
+        check_topic(&filter, true)?;
+        let timeout = self.timeout;
+        let connection = self.connection(&self_);
+        let packet_id = connection.next_packet_id();
+        connection
+            .send(&packet::subscribe(packet_id, &filter, qos_level(qos))?)
+            .await?;
+        let granted = connection
+            .wait_for(timeout, |packet| match packet {
+                Packet::SubAck {
+                    packet_id: id,
+                    return_code,
+                } if *id == packet_id => Some(*return_code),
+                _ => None,
+            })
+            .await?;
+        match granted {
+            0 => Ok(Qos::AtMostOnce),
+            1 | 2 => Ok(Qos::AtLeastOnce),
+            _ => Err(MqttError::Refused),
+        }
+    }
+
+    async fn unsubscribe(
+        &mut self,
+        self_: Resource<MqttClient>,
+        filter: String,
+    ) -> Result<(), MqttError> {
+        check_topic(&filter, true)?;
+        let timeout = self.timeout;
+        let connection = self.connection(&self_);
+        let packet_id = connection.next_packet_id();
+        connection
+            .send(&packet::unsubscribe(packet_id, &filter)?)
+            .await?;
+        connection
+            .wait_for(timeout, |packet| {
+                matches!(packet, Packet::UnsubAck(id) if *id == packet_id).then_some(())
+            })
+            .await
+    }
+
+    async fn next_message(&mut self, self_: Resource<MqttClient>) -> Result<Message, MqttError> {
+        let timeout = self.timeout;
+        let connection = self.connection(&self_);
+        loop {
+            if let Some(message) = connection.inbox.pop_front() {
+                return Ok(message);
+            }
+            if connection.closed {
+                return Err(MqttError::Disconnected);
+            }
+            let packet = match connection.keep_alive {
+                Some(keep_alive) => {
+                    // The PINGRESP may take as long as any other acknowledgement
+                    let wake_at = match connection.ping_sent {
+                        Some(ping_sent) => ping_sent + timeout,
+                        None => connection.last_sent + keep_alive,
+                    };
+                    match select(connection.read_packet(), Timer::at(wake_at)).await {
+                        Either::First(packet) => packet?,
+                        Either::Second(()) if connection.ping_sent.is_some() => {
+                            // The broker is unreachable, as the spec says the connection is
+                            // closed
+                            return connection.check(Err(MqttError::Disconnected));
+                        }
+                        Either::Second(()) => {
+                            connection.send(&packet::PINGREQ).await?;
+                            connection.ping_sent = Some(Instant::now());
+                            continue;
+                        }
+                    }
+                }
+                None => connection.read_packet().await?,
+            };
+            // Late acknowledgements of timed out requests are ignored
+            connection.dispatch(packet).await?;
+        }
+    }
+
+    async fn try_next_message(
+        &mut self,
+        self_: Resource<MqttClient>,
+    ) -> Result<Option<Message>, MqttError> {
+        let connection = self.connection(&self_);
+        loop {
+            if let Some(message) = connection.inbox.pop_front() {
+                return Ok(Some(message));
+            }
+            if let Some(packet) = connection.take_packet()? {
+                connection.dispatch(packet).await?;
+                continue;
+            }
+            if connection.closed {
+                return Err(MqttError::Disconnected);
+            }
+            if !connection.socket.can_recv() {
+                return Ok(None);
+            }
+            // Returns right away as data is available
+            connection.fill().await?;
+        }
+    }
+
+    async fn disconnect(&mut self, self_: Resource<MqttClient>) {
+        let timeout = self.timeout;
+        let connection = self.connection(&self_);
+        if connection.send(&packet::DISCONNECT).await.is_ok() {
+            connection.socket.close();
+            // The socket is aborted when dropped if the broker doesn't acknowledge in time
+            let _ = select(
+                connection.socket.run(|socket| socket.flush()),
+                Timer::after(timeout),
+            )
+            .await;
+        }
+        connection.closed = true;
+    }
+
+    fn drop(&mut self, tcp: &mut ArielTcpHost, rep: Resource<MqttClient>) {
+        if let Some(connection) = self
+            .connections
+            .get_mut(rep.rep() as usize)
+            .and_then(Option::take)
+        {
+            tcp.release(connection.socket);
+        }
+    }
+}
+
+impl Host for ArielOSHost {}
+
+impl HostMqttClient for ArielOSHost {
+    async fn connect(
+        &mut self,
+        broker: Endpoint,
+        options: ConnectOptions,
+    ) -> Result<Resource<MqttClient>, MqttError> {
+        self.before_deadline(
+            |host| host.mqtt_host.connect(&mut host.tcp_host, broker, options),
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn publish(
+        &mut self,
+        self_: Resource<MqttClient>,
+        topic: String,
+        payload: Vec<u8>,
+        qos: Qos,
+        retain: bool,
+    ) -> Result<(), MqttError> {
+        self.before_deadline(
+            |host| {
+                host.mqtt_host
+                    .publish(&mut host.tcp_host, self_, topic, payload, qos, retain)
+            },
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn subscribe(
+        &mut self,
+        self_: Resource<MqttClient>,
+        filter: String,
+        qos: Qos,
+    ) -> Result<Qos, MqttError> {
+        self.before_deadline(
+            |host| host.mqtt_host.subscribe(self_, filter, qos),
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn unsubscribe(
+        &mut self,
+        self_: Resource<MqttClient>,
+        filter: String,
+    ) -> Result<(), MqttError> {
+        self.before_deadline(
+            |host| host.mqtt_host.unsubscribe(self_, filter),
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn next_message(&mut self, self_: Resource<MqttClient>) -> Result<Message, MqttError> {
+        self.before_deadline(
+            |host| host.mqtt_host.next_message(self_),
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn try_next_message(
+        &mut self,
+        self_: Resource<MqttClient>,
+    ) -> Result<Option<Message>, MqttError> {
+        self.before_deadline(
+            |host| host.mqtt_host.try_next_message(self_),
+            Err(MqttError::TimedOut),
+        )
+        .await
+    }
+
+    async fn disconnect(&mut self, self_: Resource<MqttClient>) {
+        self.before_deadline(|host| host.mqtt_host.disconnect(self_), ())
+            .await
+    }
+
+    fn drop(&mut self, rep: Resource<MqttClient>) -> wasmtime::Result<()> {
+        self.mqtt_host.drop(&mut self.tcp_host, rep);
+        Ok(())
+    }
+}
+
+impl ArielOSHost {
+    /// Sets how long the broker may take to acknowledge a request.
+    pub fn set_mqtt_timeout(&mut self, timeout: Duration) {
+        self.mqtt_host.timeout = timeout;
+    }
+}
@@ -0,0 +1,355 @@
+//! Encoding and decoding of the MQTT 3.1.1 control packets a client needs.
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use super::MqttError;
+
+const CONNECT: u8 = 0x10;
+const CONNACK: u8 = 0x20;
+const PUBLISH: u8 = 0x30;
+const PUBACK: u8 = 0x40;
+const SUBSCRIBE: u8 = 0x82;
+const SUBACK: u8 = 0x90;
+const UNSUBSCRIBE: u8 = 0xa2;
+const UNSUBACK: u8 = 0xb0;
+const PINGRESP: u8 = 0xd0;
+
+pub(crate) const PINGREQ: [u8; 2] = [0xc0, 0];
+pub(crate) const DISCONNECT: [u8; 2] = [0xe0, 0];
+
+/// Largest packet accepted from the broker.
+pub(crate) const MAX_PACKET_LEN: usize = 4096;
+
+pub(crate) struct ConnectFields<'a> {
+    pub(crate) client_id: &'a str,
+    pub(crate) keep_alive_secs: u16,
+    pub(crate) clean_session: bool,
+    pub(crate) username: Option<&'a str>,
+    pub(crate) password: Option<&'a [u8]>,
+}
+
+pub(crate) enum Packet {
+    ConnAck {
+        return_code: u8,
+    },
+    Publish {
+        topic: String,
+        payload: Vec<u8>,
+        qos: u8,
+        retain: bool,
+        packet_id: Option<u16>,
+    },
+    PubAck(u16),
+    SubAck {
+        packet_id: u16,
+        return_code: u8,
+    },
+    UnsubAck(u16),
+    PingResp,
+}
+
+fn put_bytes(out: &mut Vec<u8>, bytes: &[u8]) -> Result<(), MqttError> {
+    let len = u16::try_from(bytes.len()).map_err(|_| MqttError::PacketTooLarge)?;
+    out.extend_from_slice(&len.to_be_bytes());
+    out.extend_from_slice(bytes);
+    Ok(())
+}
+
+/// Prepends the fixed header to `body`.
+fn finish(first_byte: u8, body: &[u8]) -> Result<Vec<u8>, MqttError> {
+    // Remaining lengths are encoded in at most four bytes
+    if body.len() >= 1 << 28 {
+        return Err(MqttError::PacketTooLarge);
+    }
+    let mut packet = Vec::with_capacity(5 + body.len());
+    packet.push(first_byte);
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+    packet.extend_from_slice(body);
+    Ok(packet)
+}
+
+pub(crate) fn connect(fields: &ConnectFields<'_>) -> Result<Vec<u8>, MqttError> {
+    let mut flags = 0;
+    if fields.clean_session {
+        flags |= 0x02;
+    }
+    if fields.password.is_some() {
+        flags |= 0x40;
+    }
+    if fields.username.is_some() {
+        flags |= 0x80;
+    }
+    let mut body = Vec::new();
+    put_bytes(&mut body, b"MQTT")?;
+    // Protocol level of MQTT 3.1.1
+    body.push(4);
+    body.push(flags);
+    body.extend_from_slice(&fields.keep_alive_secs.to_be_bytes());
+    put_bytes(&mut body, fields.client_id.as_bytes())?;
+    if let Some(username) = fields.username {
+        put_bytes(&mut body, username.as_bytes())?;
+    }
+    if let Some(password) = fields.password {
+        put_bytes(&mut body, password)?;
+    }
+    finish(CONNECT, &body)
+}
+
+pub(crate) fn publish(
+    topic: &str,
+    payload: &[u8],
+    qos: u8,
+    retain: bool,
+    packet_id: Option<u16>,
+) -> Result<Vec<u8>, MqttError> {
+    let mut body = Vec::with_capacity(4 + topic.len() + payload.len());
+    put_bytes(&mut body, topic.as_bytes())?;
+    if let Some(packet_id) = packet_id {
+        body.extend_from_slice(&packet_id.to_be_bytes());
+    }
+    body.extend_from_slice(payload);
+    finish(PUBLISH | (qos << 1) | u8::from(retain), &body)
+}
+
+pub(crate) fn puback(packet_id: u16) -> [u8; 4] {
+    let [high, low] = packet_id.to_be_bytes();
+    [PUBACK, 2, high, low]
+}
+
+pub(crate) fn subscribe(packet_id: u16, filter: &str, qos: u8) -> Result<Vec<u8>, MqttError> {
+    let mut body = Vec::with_capacity(5 + filter.len());
+    body.extend_from_slice(&packet_id.to_be_bytes());
+    put_bytes(&mut body, filter.as_bytes())?;
+    body.push(qos);
+    finish(SUBSCRIBE, &body)
+}
+
+pub(crate) fn unsubscribe(packet_id: u16, filter: &str) -> Result<Vec<u8>, MqttError> {
+    let mut body = Vec::with_capacity(4 + filter.len());
+    body.extend_from_slice(&packet_id.to_be_bytes());
+    put_bytes(&mut body, filter.as_bytes())?;
+    finish(UNSUBSCRIBE, &body)
+}
+
+fn read_u16(bytes: &[u8], at: usize) -> Result<u16, MqttError> {
+    bytes
+        .get(at..at + 2)
+        .map(|b| u16::from_be_bytes([b[0], b[1]]))
+        .ok_or(MqttError::ProtocolError)
+}
+
+/// Decodes the packet at the start of `buf`, returning it with its length, or `None` if it is
+/// not complete yet.
+pub(crate) fn decode(buf: &[u8]) -> Result<Option<(Packet, usize)>, MqttError> {
+    let Some(&first_byte) = buf.first() else {
+        return Ok(None);
+    };
+    let mut len = 0;
+    let mut header_len = 1;
+    loop {
+        let Some(&byte) = buf.get(header_len) else {
+            return Ok(None);
+        };
+        len |= usize::from(byte & 0x7f) << (7 * (header_len - 1));
+        header_len += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+        if header_len > 4 {
+            return Err(MqttError::ProtocolError);
+        }
+    }
+    if header_len + len > MAX_PACKET_LEN {
+        return Err(MqttError::PacketTooLarge);
+    }
+    let Some(body) = buf.get(header_len..header_len + len) else {
+        return Ok(None);
+    };
+
+    let packet = match first_byte & 0xf0 {
+        CONNACK => Packet::ConnAck {
+            return_code: *body.get(1).ok_or(MqttError::ProtocolError)?,
+        },
+        PUBLISH => {
+            let qos = (first_byte >> 1) & 0x03;
+            let topic_len = usize::from(read_u16(body, 0)?);
+            let topic = body
+                .get(2..2 + topic_len)
+                .and_then(|topic| core::str::from_utf8(topic).ok())
+                .ok_or(MqttError::ProtocolError)?;
+            let (packet_id, payload_start) = match qos {
+                0 => (None, 2 + topic_len),
+                // Only QoS 0 and 1 are subscribed to
+                1 => (Some(read_u16(body, 2 + topic_len)?), 4 + topic_len),
+                _ => return Err(MqttError::ProtocolError),
+            };
+            Packet::Publish {
+                topic: String::from(topic),
+                payload: body
+                    .get(payload_start..)
+                    .ok_or(MqttError::ProtocolError)?
+                    .to_vec(),
+                qos,
+                retain: first_byte & 0x01 != 0,
+                packet_id,
+            }
+        }
+        PUBACK => Packet::PubAck(read_u16(body, 0)?),
+        SUBACK => Packet::SubAck {
+            packet_id: read_u16(body, 0)?,
+            return_code: *body.get(2).ok_or(MqttError::ProtocolError)?,
+        },
+        UNSUBACK => Packet::UnsubAck(read_u16(body, 0)?),
+        PINGRESP => Packet::PingResp,
+        _ => return Err(MqttError::ProtocolError),
+    };
+    Ok(Some((packet, header_len + len)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The remaining length of an encoded packet.
+    fn remaining_length(packet: &[u8]) -> &[u8] {
+        let end = packet[1..]
+            .iter()
+            .position(|byte| byte & 0x80 == 0)
+            .unwrap();
+        &packet[1..end + 2]
+    }
+
+    #[test]
+    fn encodes_remaining_lengths() {
+        for (len, encoded) in [
+            (0, &[0x00][..]),
+            (127, &[0x7f]),
+            (128, &[0x80, 0x01]),
+            (16_383, &[0xff, 0x7f]),
+            (16_384, &[0x80, 0x80, 0x01]),
+            (2_097_151, &[0xff, 0xff, 0x7f]),
+            (2_097_152, &[0x80, 0x80, 0x80, 0x01]),
+        ] {
+            let packet = finish(PUBLISH, &alloc::vec![0; len]).unwrap();
+            assert_eq!(remaining_length(&packet), encoded);
+            assert_eq!(packet.len(), 1 + encoded.len() + len);
+        }
+    }
+
+    #[test]
+    fn decodes_remaining_lengths() {
+        for payload_len in [123, 124, 4000] {
+            let packet = publish("a/b", &alloc::vec![7; payload_len], 0, false, None).unwrap();
+            let Ok(Some((Packet::Publish { topic, payload, .. }, len))) = decode(&packet) else {
+                panic!("A complete packet should decode");
+            };
+            assert_eq!(
+                (topic.as_str(), payload.len(), len),
+                ("a/b", payload_len, packet.len())
+            );
+        }
+
+        // Largest remaining length, and one byte too many
+        assert_eq!(
+            decode(&[PUBLISH, 0xff, 0xff, 0xff, 0x7f]).err(),
+            Some(MqttError::PacketTooLarge)
+        );
+        assert_eq!(
+            decode(&[PUBLISH, 0xff, 0xff, 0xff, 0xff, 0x7f]).err(),
+            Some(MqttError::ProtocolError)
+        );
+        // Rejected before the packet arrives
+        assert_eq!(
+            decode(&[PUBLISH, 0xff, 0x7f]).err(),
+            Some(MqttError::PacketTooLarge)
+        );
+    }
+
+    #[test]
+    fn waits_for_truncated_packets() {
+        let packet = publish("a/b", &alloc::vec![7; 200], 1, true, Some(9)).unwrap();
+        for len in 0..packet.len() {
+            assert!(matches!(decode(&packet[..len]), Ok(None)), "{len} bytes");
+        }
+
+        let mut two = packet.clone();
+        two.extend_from_slice(&puback(3));
+        let Ok(Some((
+            Packet::Publish {
+                qos,
+                retain,
+                packet_id,
+                ..
+            },
+            len,
+        ))) = decode(&two)
+        else {
+            panic!("The first packet should decode");
+        };
+        assert_eq!(
+            (qos, retain, packet_id, len),
+            (1, true, Some(9), packet.len())
+        );
+        assert!(matches!(
+            decode(&two[len..]),
+            Ok(Some((Packet::PubAck(3), 4)))
+        ));
+    }
+
+    #[test]
+    fn rejects_truncated_bodies() {
+        for packet in [
+            // CONNACK without return code
+            &[CONNACK, 1, 0][..],
+            // PUBLISH with a topic longer than the packet
+            &[PUBLISH, 4, 0, 3, b'a', b'b'],
+            // QoS 1 PUBLISH without packet identifier
+            &[PUBLISH | 0x02, 4, 0, 1, b'a', 0],
+            // QoS 2 isn't subscribed to
+            &[PUBLISH | 0x04, 5, 0, 1, b'a', 0, 1],
+            &[PUBACK, 1, 0],
+            &[SUBACK, 2, 0, 1],
+            &[UNSUBACK, 0],
+            &[CONNECT, 0],
+        ] {
+            assert_eq!(decode(packet).err(), Some(MqttError::ProtocolError));
+        }
+    }
+
+    #[test]
+    fn decodes_acknowledgements() {
+        assert!(matches!(
+            decode(&[CONNACK, 2, 0, 5]),
+            Ok(Some((Packet::ConnAck { return_code: 5 }, 4)))
+        ));
+        assert!(matches!(
+            decode(&[SUBACK, 3, 0, 2, 1]),
+            Ok(Some((
+                Packet::SubAck {
+                    packet_id: 2,
+                    return_code: 1
+                },
+                5
+            )))
+        ));
+        assert!(matches!(
+            decode(&[UNSUBACK, 2, 1, 0]),
+            Ok(Some((Packet::UnsubAck(256), 4)))
+        ));
+        assert!(matches!(
+            decode(&[PINGRESP, 0]),
+            Ok(Some((Packet::PingResp, 2)))
+        ));
+    }
+}
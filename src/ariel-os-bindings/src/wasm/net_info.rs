@@ -0,0 +1,130 @@
+use ariel_os_embassy::reexports::embassy_net::Ipv4Address;
+#[cfg(feature = "ipv6")]
+use ariel_os_embassy::reexports::embassy_net::Ipv6Address;
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+use super::ip_types::Ipv4Addr;
+#[cfg(feature = "ipv6")]
+use super::ip_types::Ipv6Addr;
+use super::local::Local;
+
+bindgen!({
+    world: "ariel:wasm-bindings/net-info",
+    path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+    },
+    imports: {
+        "ariel:wasm-bindings/net-info-api.wait-link": async,
+    }
+});
+
+pub use ariel::wasm_bindings::net_info_api::{
+    self as gen_net_info, Host, HostWithStore, Ipv4Config, Ipv6Config, add_to_linker,
+};
+
+/// Network status of a capsule.
+///
+/// Without a stack bound by the host, the link is reported down and never comes up.
+#[derive(Default)]
+pub(crate) struct ArielNetInfoHost {
+    stack: Option<Local<ariel_os_embassy::NetworkStack>>,
+}
+
+fn ipv4(addr: Ipv4Address) -> Ipv4Addr {
+    let [a, b, c, d] = addr.octets();
+    Ipv4Addr { a, b, c, d }
+}
+
+#[cfg(feature = "ipv6")]
+fn ipv6(addr: Ipv6Address) -> Ipv6Addr {
+    let [a, b, c, d, e, f, g, h] = addr.segments();
+    Ipv6Addr {
+        a,
+        b,
+        c,
+        d,
+        e,
+        f,
+        g,
+        h,
+    }
+}
+
+impl Host for ArielNetInfoHost {
+    fn link_up(&mut self) -> bool {
+        self.stack.is_some_and(|stack| stack.is_link_up())
+    }
+
+    fn ipv4(&mut self) -> Option<Ipv4Config> {
+        let config = self.stack?.config_v4()?;
+        Some(Ipv4Config {
+            address: ipv4(config.address.address()),
+            prefix_len: config.address.prefix_len(),
+            gateway: config.gateway.map(ipv4),
+            dns_servers: config.dns_servers.into_iter().map(ipv4).collect(),
+        })
+    }
+
+    #[cfg(feature = "ipv6")]
+    fn ipv6(&mut self) -> Option<Ipv6Config> {
+        let config = self.stack?.config_v6()?;
+        Some(Ipv6Config {
+            address: ipv6(config.address.address()),
+            prefix_len: config.address.prefix_len(),
+            gateway: config.gateway.map(ipv6),
+            dns_servers: config.dns_servers.into_iter().map(ipv6).collect(),
+        })
+    }
+
+    #[cfg(not(feature = "ipv6"))]
+    fn ipv6(&mut self) -> Option<Ipv6Config> {
+        None
+    }
+
+    async fn wait_link(&mut self, up: bool) {
+        // Without a stack, the link stays down for good: there is nothing to wait for
+        let Some(mut stack) = self.stack else {
+            return;
+        };
+        if up {
+            stack.run(|stack| stack.wait_link_up()).await;
+        } else {
+            stack.run(|stack| stack.wait_link_down()).await;
+        }
+    }
+}
+
+impl Host for ArielOSHost {
+    fn link_up(&mut self) -> bool {
+        self.net_info_host.link_up()
+    }
+
+    fn ipv4(&mut self) -> Option<Ipv4Config> {
+        self.net_info_host.ipv4()
+    }
+
+    fn ipv6(&mut self) -> Option<Ipv6Config> {
+        self.net_info_host.ipv6()
+    }
+
+    async fn wait_link(&mut self, up: bool) {
+        self.before_deadline(|host| host.net_info_host.wait_link(up), ())
+            .await;
+    }
+}
+
+impl ArielOSHost {
+    /// Lets the capsule observe the state and configuration of `stack`.
+    ///
+    /// # Safety
+    ///
+    /// The host must only be used on the executor running `stack`, whose state can't be read
+    /// from other threads or cores.
+    pub unsafe fn bind_net_info(&mut self, stack: ariel_os_embassy::NetworkStack) {
+        // SAFETY: Required from the caller
+        self.net_info_host.stack = Some(unsafe { Local::new(stack) });
+    }
+}
@@ -0,0 +1,274 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use core::ops::RangeInclusive;
+
+use ariel_os_embassy::api::time::{Duration, Instant};
+use ariel_os_embassy::reexports::embassy_net::{IpAddress, IpEndpoint};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PolicyError {
+    /// The policy doesn't allow this port or destination.
+    Denied,
+    /// The capsule exceeded its packet or byte rate.
+    RateLimited,
+}
+
+#[derive(Clone)]
+struct RemoteRule {
+    prefix: IpAddress,
+    prefix_len: u8,
+    ports: RangeInclusive<u16>,
+}
+
+impl RemoteRule {
+    fn matches(&self, endpoint: &IpEndpoint) -> bool {
+        self.ports.contains(&endpoint.port)
+            && prefix_matches(&self.prefix, self.prefix_len, &endpoint.addr)
+    }
+}
+
+fn prefix_matches(prefix: &IpAddress, len: u8, addr: &IpAddress) -> bool {
+    match (prefix, addr) {
+        (IpAddress::Ipv4(prefix), IpAddress::Ipv4(addr)) => {
+            let mask = u32::MAX
+                .checked_shl(32 - u32::from(len.min(32)))
+                .unwrap_or(0);
+            u32::from(*prefix) & mask == u32::from(*addr) & mask
+        }
+        #[cfg(feature = "ipv6")]
+        (IpAddress::Ipv6(prefix), IpAddress::Ipv6(addr)) => {
+            let mask = u128::MAX
+                .checked_shl(128 - u32::from(len.min(128)))
+                .unwrap_or(0);
+            u128::from(*prefix) & mask == u128::from(*addr) & mask
+        }
+        #[allow(unreachable_patterns, reason = "Conditional compilation")]
+        _ => false,
+    }
+}
+
+#[derive(Clone)]
+struct RateLimit {
+    packets_per_second: u32,
+    bytes_per_second: u32,
+    window_start: Instant,
+    packets: u32,
+    bytes: u32,
+}
+
+/// Which ports a capsule may bind, which endpoints it may reach and how much it may send.
+///
+/// Without any rule every port and endpoint is allowed and the rate is unlimited, each rule
+/// restricts the corresponding kind of access to what it allows.
+#[derive(Clone, Default)]
+pub struct NetPolicy {
+    local_ports: Option<Vec<RangeInclusive<u16>>>,
+    remotes: Option<Vec<RemoteRule>>,
+    rate: Option<RateLimit>,
+}
+
+impl NetPolicy {
+    /// A policy allowing everything.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allows binding the local `ports`.
+    pub fn allow_local_ports(mut self, ports: RangeInclusive<u16>) -> Self {
+        self.local_ports.get_or_insert_with(Vec::new).push(ports);
+        self
+    }
+
+    /// Allows reaching `ports` of the addresses starting with the `prefix_len` bits of `prefix`.
+    pub fn allow_remote(
+        mut self,
+        prefix: IpAddress,
+        prefix_len: u8,
+        ports: RangeInclusive<u16>,
+    ) -> Self {
+        self.remotes.get_or_insert_with(Vec::new).push(RemoteRule {
+            prefix,
+            prefix_len,
+            ports,
+        });
+        self
+    }
+
+    /// Limits what the capsule sends to `packets_per_second` and `bytes_per_second`.
+    pub fn limit_rate(mut self, packets_per_second: u32, bytes_per_second: u32) -> Self {
+        self.rate = Some(RateLimit {
+            packets_per_second,
+            bytes_per_second,
+            window_start: Instant::MIN,
+            packets: 0,
+            bytes: 0,
+        });
+        self
+    }
+
+    pub(crate) fn check_local_port(&self, port: u16) -> Result<(), PolicyError> {
+        match &self.local_ports {
+            Some(ranges) if !ranges.iter().any(|r| r.contains(&port)) => Err(PolicyError::Denied),
+            _ => Ok(()),
+        }
+    }
+
+    pub(crate) fn check_remote(&self, endpoint: &IpEndpoint) -> Result<(), PolicyError> {
+        match &self.remotes {
+            Some(rules) if !rules.iter().any(|r| r.matches(endpoint)) => Err(PolicyError::Denied),
+            _ => Ok(()),
+        }
+    }
+
+    /// Accounts for sending `len` bytes, or fails if that exceeds the rate limit.
+    ///
+    /// Rates are measured over fixed windows of one second.
+    pub(crate) fn consume(&mut self, len: usize) -> Result<(), PolicyError> {
+        self.consume_at(len, Instant::now())
+    }
+
+    fn consume_at(&mut self, len: usize, now: Instant) -> Result<(), PolicyError> {
+        let Some(rate) = &mut self.rate else {
+            return Ok(());
+        };
+        if now - rate.window_start >= Duration::from_secs(1) {
+            rate.window_start = now;
+            rate.packets = 0;
+            rate.bytes = 0;
+        }
+        let bytes = rate
+            .bytes
+            .saturating_add(u32::try_from(len).unwrap_or(u32::MAX));
+        if rate.packets >= rate.packets_per_second || bytes > rate.bytes_per_second {
+            return Err(PolicyError::RateLimited);
+        }
+        rate.packets += 1;
+        rate.bytes = bytes;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ADDR: IpAddress = IpAddress::v4(192, 0, 2, 1);
+
+    fn endpoint(addr: IpAddress, port: u16) -> IpEndpoint {
+        IpEndpoint::new(addr, port)
+    }
+
+    #[test]
+    fn allows_everything_without_rules() {
+        let mut policy = NetPolicy::new();
+        assert_eq!(policy.check_local_port(1), Ok(()));
+        assert_eq!(policy.check_remote(&endpoint(ADDR, 1)), Ok(()));
+        assert_eq!(policy.consume(usize::MAX), Ok(()));
+    }
+
+    #[test]
+    fn matches_ipv4_prefixes() {
+        let any = NetPolicy::new().allow_remote(IpAddress::v4(0, 0, 0, 0), 0, 0..=u16::MAX);
+        assert_eq!(any.check_remote(&endpoint(ADDR, 80)), Ok(()));
+        assert_eq!(
+            any.check_remote(&endpoint(IpAddress::v4(255, 255, 255, 255), 80)),
+            Ok(())
+        );
+
+        let subnet = NetPolicy::new().allow_remote(IpAddress::v4(192, 0, 2, 0), 24, 0..=u16::MAX);
+        assert_eq!(subnet.check_remote(&endpoint(ADDR, 80)), Ok(()));
+        assert_eq!(
+            subnet.check_remote(&endpoint(IpAddress::v4(192, 0, 3, 1), 80)),
+            Err(PolicyError::Denied)
+        );
+
+        let host = NetPolicy::new().allow_remote(ADDR, 32, 0..=u16::MAX);
+        assert_eq!(host.check_remote(&endpoint(ADDR, 80)), Ok(()));
+        assert_eq!(
+            host.check_remote(&endpoint(IpAddress::v4(192, 0, 2, 2), 80)),
+            Err(PolicyError::Denied)
+        );
+        // Longer prefixes are the whole address
+        let longer = NetPolicy::new().allow_remote(ADDR, 64, 0..=u16::MAX);
+        assert_eq!(longer.check_remote(&endpoint(ADDR, 80)), Ok(()));
+    }
+
+    #[cfg(feature = "ipv6")]
+    #[test]
+    fn matches_ipv6_prefixes() {
+        const V6: IpAddress = IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 1);
+
+        let any =
+            NetPolicy::new().allow_remote(IpAddress::v6(0, 0, 0, 0, 0, 0, 0, 0), 0, 0..=u16::MAX);
+        assert_eq!(any.check_remote(&endpoint(V6, 80)), Ok(()));
+
+        let host = NetPolicy::new().allow_remote(V6, 128, 0..=u16::MAX);
+        assert_eq!(host.check_remote(&endpoint(V6, 80)), Ok(()));
+        assert_eq!(
+            host.check_remote(&endpoint(
+                IpAddress::v6(0x2001, 0xdb8, 0, 0, 0, 0, 0, 2),
+                80
+            )),
+            Err(PolicyError::Denied)
+        );
+
+        // Rules never match the other family
+        assert_eq!(
+            any.check_remote(&endpoint(ADDR, 80)),
+            Err(PolicyError::Denied)
+        );
+    }
+
+    #[test]
+    fn port_ranges_are_inclusive() {
+        let policy = NetPolicy::new()
+            .allow_local_ports(5683..=5684)
+            .allow_remote(ADDR, 32, 1000..=2000);
+        for (port, allowed) in [(5682, false), (5683, true), (5684, true), (5685, false)] {
+            assert_eq!(policy.check_local_port(port).is_ok(), allowed, "{port}");
+        }
+        for (port, allowed) in [(999, false), (1000, true), (2000, true), (2001, false)] {
+            assert_eq!(
+                policy.check_remote(&endpoint(ADDR, port)).is_ok(),
+                allowed,
+                "{port}"
+            );
+        }
+    }
+
+    #[test]
+    fn limits_packets() {
+        let mut policy = NetPolicy::new().limit_rate(2, u32::MAX);
+        let start = Instant::from_secs(10);
+        assert_eq!(policy.consume_at(1, start), Ok(()));
+        assert_eq!(policy.consume_at(1, start), Ok(()));
+        assert_eq!(policy.consume_at(1, start), Err(PolicyError::RateLimited));
+    }
+
+    #[test]
+    fn limits_bytes() {
+        let mut policy = NetPolicy::new().limit_rate(u32::MAX, 100);
+        let start = Instant::from_secs(10);
+        assert_eq!(policy.consume_at(60, start), Ok(()));
+        assert_eq!(policy.consume_at(50, start), Err(PolicyError::RateLimited));
+        // Rejected sends aren't accounted for
+        assert_eq!(policy.consume_at(40, start), Ok(()));
+        assert_eq!(policy.consume_at(1, start), Err(PolicyError::RateLimited));
+        assert_eq!(policy.consume_at(0, start), Ok(()));
+    }
+
+    #[test]
+    fn window_resets_after_a_second() {
+        let mut policy = NetPolicy::new().limit_rate(1, 100);
+        let start = Instant::from_secs(10);
+        assert_eq!(policy.consume_at(100, start), Ok(()));
+
+        let almost = start + Duration::from_millis(999);
+        assert_eq!(policy.consume_at(1, almost), Err(PolicyError::RateLimited));
+
+        let next = start + Duration::from_secs(1);
+        assert_eq!(policy.consume_at(100, next), Ok(()));
+        assert_eq!(policy.consume_at(1, next), Err(PolicyError::RateLimited));
+    }
+}
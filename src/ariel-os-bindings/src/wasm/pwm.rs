@@ -0,0 +1,147 @@
+extern crate alloc;
+use alloc::boxed::Box;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/pwm",
+    path: "../../wit/",
+});
+
+pub use ariel::wasm_bindings::pwm_api::{self as gen_pwm, Host, HostWithStore, add_to_linker};
+
+use gen_pwm::PwmError;
+
+/// Duty cycle of a channel that is always on, in per-mille.
+pub const FULL_DUTY_CYCLE: u16 = 1000;
+
+/// The PWM peripheral refused a setting, reported to the capsule as `hardware`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PwmChannelError;
+
+/// A PWM output the firmware hands over to a capsule.
+///
+/// This is implemented by the firmware on top of the PWM peripheral of its HAL. A capsule may
+/// drive its channels from any task its store is moved to, hence the `Send` bound.
+pub trait PwmChannel: Send {
+    /// Sets the duty cycle, in per-mille of the period.
+    fn set_duty_cycle(&mut self, per_mille: u16) -> Result<(), PwmChannelError>;
+
+    /// Sets the frequency of the output.
+    fn set_frequency(&mut self, hertz: u32) -> Result<(), PwmChannelError>;
+}
+
+/// Safe range of the settings of a channel, requested values are clamped into it.
+#[derive(Debug, Clone, Copy)]
+pub struct PwmLimits {
+    pub min_duty_cycle: u16,
+    pub max_duty_cycle: u16,
+    pub min_frequency: u32,
+    pub max_frequency: u32,
+}
+
+impl Default for PwmLimits {
+    fn default() -> Self {
+        Self {
+            min_duty_cycle: 0,
+            max_duty_cycle: FULL_DUTY_CYCLE,
+            min_frequency: 1,
+            max_frequency: u32::MAX,
+        }
+    }
+}
+
+struct RegisteredChannel {
+    name: String,
+    channel: Box<dyn PwmChannel>,
+    limits: PwmLimits,
+}
+
+#[derive(Default)]
+pub(crate) struct ArielPwmHost {
+    channels: Vec<RegisteredChannel>,
+}
+
+impl ArielPwmHost {
+    fn channel(&mut self, name: &str) -> Result<&mut RegisteredChannel, PwmError> {
+        self.channels
+            .iter_mut()
+            .find(|c| c.name == name)
+            .ok_or(PwmError::NoChannel)
+    }
+}
+
+impl Host for ArielPwmHost {
+    fn channels(&mut self) -> Vec<String> {
+        self.channels.iter().map(|c| c.name.clone()).collect()
+    }
+
+    fn set_duty_cycle(&mut self, channel: String, per_mille: u16) -> Result<u16, PwmError> {
+        let registered = self.channel(&channel)?;
+        let limits = registered.limits;
+        let per_mille = per_mille.clamp(limits.min_duty_cycle, limits.max_duty_cycle);
+        registered
+            .channel
+            .set_duty_cycle(per_mille)
+            .map_err(|_| PwmError::Hardware)?;
+        Ok(per_mille)
+    }
+
+    fn set_frequency(&mut self, channel: String, hertz: u32) -> Result<u32, PwmError> {
+        let registered = self.channel(&channel)?;
+        let limits = registered.limits;
+        let hertz = hertz.clamp(limits.min_frequency, limits.max_frequency);
+        registered
+            .channel
+            .set_frequency(hertz)
+            .map_err(|_| PwmError::Hardware)?;
+        Ok(hertz)
+    }
+}
+
+impl Host for ArielOSHost {
+    fn channels(&mut self) -> Vec<String> {
+        self.pwm_host.channels()
+    }
+
+    fn set_duty_cycle(&mut self, channel: String, per_mille: u16) -> Result<u16, PwmError> {
+        self.pwm_host.set_duty_cycle(channel, per_mille)
+    }
+
+    fn set_frequency(&mut self, channel: String, hertz: u32) -> Result<u32, PwmError> {
+        self.pwm_host.set_frequency(channel, hertz)
+    }
+}
+
+impl ArielOSHost {
+    /// Registers a PWM channel the capsule can drive under `name`, within `limits`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the limits describe an empty range.
+    pub fn bind_pwm_channel(
+        &mut self,
+        name: &str,
+        channel: impl PwmChannel + 'static,
+        limits: PwmLimits,
+    ) {
+        assert!(
+            limits.min_duty_cycle <= limits.max_duty_cycle.min(FULL_DUTY_CYCLE)
+                && limits.min_frequency <= limits.max_frequency,
+            "Invalid PWM limits"
+        );
+        self.pwm_host.channels.retain(|c| c.name != name);
+        self.pwm_host.channels.push(RegisteredChannel {
+            name: String::from(name),
+            channel: Box::new(channel),
+            limits: PwmLimits {
+                max_duty_cycle: limits.max_duty_cycle.min(FULL_DUTY_CYCLE),
+                ..limits
+            },
+        });
+    }
+}
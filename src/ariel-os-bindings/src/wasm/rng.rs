@@ -3,7 +3,6 @@ use ariel_os_random::{FastRngSend, fast_rng_send};
 extern crate alloc;
 
 use alloc::vec::Vec;
-use rand_core::RngCore as _;
 
 use wasmtime::component::{Resource, bindgen};
 
@@ -16,14 +15,56 @@ bindgen!({
 
 pub use ariel::wasm_bindings::rng_api::{Host, HostRNG, HostWithStore, RNG, add_to_linker};
 
+/// Deterministic PRNG (SplitMix64) used to replay the runs of a capsule.
+struct SeededRng {
+    state: u64,
+}
+
+impl rand_core::RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        rand_core::impls::fill_bytes_via_next(self, dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+enum RngSource {
+    Fast(FastRngSend),
+    Seeded(SeededRng),
+}
+
 pub struct ArielRNGHost {
-    rng: FastRngSend,
+    rng: RngSource,
 }
 
 impl Default for ArielRNGHost {
     fn default() -> Self {
         Self {
-            rng: fast_rng_send(),
+            rng: RngSource::Fast(fast_rng_send()),
+        }
+    }
+}
+
+impl ArielRNGHost {
+    fn rng(&mut self) -> &mut dyn rand_core::RngCore {
+        match &mut self.rng {
+            RngSource::Fast(rng) => rng,
+            RngSource::Seeded(rng) => rng,
         }
     }
 }
@@ -32,14 +73,14 @@ impl Host for ArielRNGHost {}
 
 impl HostRNG for ArielRNGHost {
     fn next_u32(&mut self) -> u32 {
-        self.rng.next_u32()
+        self.rng().next_u32()
     }
     fn next_u64(&mut self) -> u64 {
-        self.rng.next_u64()
+        self.rng().next_u64()
     }
     fn random_bytes(&mut self, len: u32) -> Vec<u8> {
         let mut dest: Vec<u8> = core::iter::repeat_n(0, len as usize).collect();
-        self.rng.fill_bytes(&mut dest);
+        self.rng().fill_bytes(&mut dest);
         dest
     }
     fn drop(&mut self, _: Resource<RNG>) -> wasmtime::Result<()> {
@@ -63,3 +104,12 @@ impl HostRNG for ArielOSHost {
         self.rng_host.drop(rep)
     }
 }
+
+impl ArielOSHost {
+    /// Replaces the RNG of the capsule with a deterministic one seeded with `seed`.
+    ///
+    /// The same seed always produces the same sequence, on device as in host-side tests.
+    pub fn seed_rng(&mut self, seed: u64) {
+        self.rng_host.rng = RngSource::Seeded(SeededRng { state: seed });
+    }
+}
@@ -62,42 +62,8 @@ impl Host for ArielOSHost {
         &mut self,
         label: Option<comp_sensor::Label>,
     ) -> Result<Vec<(comp_sensor::Sample, comp_sensor::Channel)>, ()> {
-        let mut results = Vec::new();
-        for sensor in REGISTRY.sensors() {
-            match sensor.wait_for_reading().await {
-                // Sensor could have been filtered out before
-                Err(ReadingError::NotMeasuring) => {
-                    ariel_os_debug::log::debug!(
-                        "Sensor {:?} of categories {:?} wasn't measuring, possibly because it was filtered out before",
-                        sensor.display_name(),
-                        sensor.categories()
-                    );
-                    continue;
-                }
-                Ok(samples) => match label {
-                    Some(label) => {
-                        for (reading_channel, sample) in
-                            samples.samples().filter(|(r, _)| r.label() == label.into())
-                        {
-                            results.push((
-                                comp_sensor::Sample::from(sample),
-                                comp_sensor::Channel::from(reading_channel),
-                            ))
-                        }
-                    }
-                    None => {
-                        for (reading_channel, sample) in samples.samples() {
-                            results.push((
-                                comp_sensor::Sample::from(sample),
-                                comp_sensor::Channel::from(reading_channel),
-                            ))
-                        }
-                    }
-                },
-                Err(_error) => return Err(()),
-            }
-        }
-        Ok(results)
+        self.before_deadline(|_| wait_for_reading(label), Err(()))
+            .await
     }
 
     #[cfg(not(feature = "sensors-async"))]
@@ -144,6 +110,49 @@ impl Host for ArielOSHost {
     }
 }
 
+/// Waits for the readings of all measuring sensors, optionally filtered by `label`.
+#[cfg(feature = "sensors-async")]
+async fn wait_for_reading(
+    label: Option<comp_sensor::Label>,
+) -> Result<Vec<(comp_sensor::Sample, comp_sensor::Channel)>, ()> {
+    let mut results = Vec::new();
+    for sensor in REGISTRY.sensors() {
+        match sensor.wait_for_reading().await {
+            // Sensor could have been filtered out before
+            Err(ReadingError::NotMeasuring) => {
+                ariel_os_debug::log::debug!(
+                    "Sensor {:?} of categories {:?} wasn't measuring, possibly because it was filtered out before",
+                    sensor.display_name(),
+                    sensor.categories()
+                );
+                continue;
+            }
+            Ok(samples) => match label {
+                Some(label) => {
+                    for (reading_channel, sample) in
+                        samples.samples().filter(|(r, _)| r.label() == label.into())
+                    {
+                        results.push((
+                            comp_sensor::Sample::from(sample),
+                            comp_sensor::Channel::from(reading_channel),
+                        ))
+                    }
+                }
+                None => {
+                    for (reading_channel, sample) in samples.samples() {
+                        results.push((
+                            comp_sensor::Sample::from(sample),
+                            comp_sensor::Channel::from(reading_channel),
+                        ))
+                    }
+                }
+            },
+            Err(_error) => return Err(()),
+        }
+    }
+    Ok(results)
+}
+
 impl From<Category> for comp_sensor::Category {
     fn from(value: Category) -> Self {
         match value {
@@ -0,0 +1,251 @@
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use ariel_os_embassy::spi::main::SpiDevice as ArielSpiDevice;
+
+use embedded_hal::spi::{Error as _, ErrorKind};
+use embedded_hal_async::spi::{Operation, SpiDevice as _};
+
+use wasmtime::component::{Resource, bindgen};
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/spi",
+    path: "../../wit/",
+    imports: {
+        "ariel:wasm-bindings/spi-api.[method]spi-device.transfer": async,
+        "ariel:wasm-bindings/spi-api.[method]spi-device.write": async,
+        "ariel:wasm-bindings/spi-api.[method]spi-device.read": async,
+        "ariel:wasm-bindings/spi-api.[method]spi-device.transaction": async,
+    }
+});
+
+pub use ariel::wasm_bindings::spi_api::{
+    self as gen_spi, Host, HostSpiDevice, HostWithStore, SpiDevice, add_to_linker,
+};
+
+use gen_spi::SpiError;
+
+const DEFAULT_MAX_READ_LEN: usize = 256;
+
+/// Chip-select devices granted to a capsule.
+///
+/// The representation of a [`SpiDevice`] resource is the index of the device in `devices`.
+pub(crate) struct ArielSpiHost {
+    pub(crate) devices: Vec<(String, ArielSpiDevice)>,
+    /// Most bytes read by a single call, the buffers are allocated by the host.
+    max_read_len: usize,
+}
+
+impl Default for ArielSpiHost {
+    fn default() -> Self {
+        Self {
+            devices: Vec::new(),
+            max_read_len: DEFAULT_MAX_READ_LEN,
+        }
+    }
+}
+
+/// Zeroed buffer for a read of `len` bytes, if that is at most `max_len`.
+fn read_buffer(len: u32, max_len: usize) -> Result<Vec<u8>, SpiError> {
+    if len as usize > max_len {
+        return Err(SpiError::TooLong);
+    }
+    Ok(core::iter::repeat_n(0, len as usize).collect())
+}
+
+impl ArielSpiHost {
+    fn device(&mut self, handle: &Resource<SpiDevice>) -> Result<&mut ArielSpiDevice, SpiError> {
+        self.devices
+            .get_mut(handle.rep() as usize)
+            .map(|(_, device)| device)
+            .ok_or(SpiError::NotAllowed)
+    }
+}
+
+impl From<ErrorKind> for SpiError {
+    fn from(value: ErrorKind) -> Self {
+        match value {
+            ErrorKind::Overrun => SpiError::Overrun,
+            ErrorKind::ModeFault => SpiError::ModeFault,
+            ErrorKind::FrameFormat => SpiError::FrameFormat,
+            ErrorKind::ChipSelectFault => SpiError::ChipSelectFault,
+            _ => SpiError::Other,
+        }
+    }
+}
+
+impl Host for ArielSpiHost {}
+
+impl HostSpiDevice for ArielSpiHost {
+    fn open(&mut self, name: String) -> Result<Resource<SpiDevice>, SpiError> {
+        self.devices
+            .iter()
+            .position(|(n, _)| *n == name)
+            .map(|index| Resource::new_own(index as u32))
+            .ok_or(SpiError::NotAllowed)
+    }
+
+    async fn transfer(
+        &mut self,
+        self_: Resource<SpiDevice>,
+        mut data: Vec<u8>,
+    ) -> Result<Vec<u8>, SpiError> {
+        self.device(&self_)?
+            .transfer_in_place(&mut data)
+            .await
+            .map_err(|e| SpiError::from(e.kind()))?;
+        Ok(data)
+    }
+
+    async fn write(&mut self, self_: Resource<SpiDevice>, data: Vec<u8>) -> Result<(), SpiError> {
+        self.device(&self_)?
+            .write(&data)
+            .await
+            .map_err(|e| e.kind().into())
+    }
+
+    async fn read(&mut self, self_: Resource<SpiDevice>, len: u32) -> Result<Vec<u8>, SpiError> {
+        let max_len = self.max_read_len;
+        let device = self.device(&self_)?;
+        let mut buf = read_buffer(len, max_len)?;
+        device
+            .read(&mut buf)
+            .await
+            .map_err(|e| SpiError::from(e.kind()))?;
+        Ok(buf)
+    }
+
+    async fn transaction(
+        &mut self,
+        self_: Resource<SpiDevice>,
+        operations: Vec<gen_spi::Operation>,
+    ) -> Result<Vec<Vec<u8>>, SpiError> {
+        let mut budget = self.max_read_len;
+        let device = self.device(&self_)?;
+
+        // Operations that produce data own their buffer, delays get an empty one. All reads
+        // together are bounded like a single one.
+        let mut buffers: Vec<(gen_spi::Operation, Vec<u8>)> = operations
+            .into_iter()
+            .map(|op| match op {
+                gen_spi::Operation::Read(len) => {
+                    let buf = read_buffer(len, budget)?;
+                    budget -= buf.len();
+                    Ok((op, buf))
+                }
+                gen_spi::Operation::Write(data) => {
+                    Ok((gen_spi::Operation::Write(Vec::new()), data))
+                }
+                gen_spi::Operation::Transfer(data) => {
+                    Ok((gen_spi::Operation::Transfer(Vec::new()), data))
+                }
+                gen_spi::Operation::DelayNs(_) => Ok((op, Vec::new())),
+            })
+            .collect::<Result<_, SpiError>>()?;
+
+        let mut ops: Vec<Operation<'_, u8>> = buffers
+            .iter_mut()
+            .map(|(op, buf)| match op {
+                gen_spi::Operation::Read(_) => Operation::Read(buf.as_mut_slice()),
+                gen_spi::Operation::Write(_) => Operation::Write(buf.as_slice()),
+                gen_spi::Operation::Transfer(_) => Operation::TransferInPlace(buf.as_mut_slice()),
+                gen_spi::Operation::DelayNs(ns) => Operation::DelayNs(*ns),
+            })
+            .collect();
+
+        device
+            .transaction(&mut ops)
+            .await
+            .map_err(|e| SpiError::from(e.kind()))?;
+        drop(ops);
+
+        Ok(buffers
+            .into_iter()
+            .filter_map(|(op, buf)| match op {
+                gen_spi::Operation::Read(_) | gen_spi::Operation::Transfer(_) => Some(buf),
+                _ => None,
+            })
+            .collect())
+    }
+
+    fn drop(&mut self, _: Resource<SpiDevice>) -> wasmtime::Result<()> {
+        // Devices stay granted to the capsule, there is nothing to release
+        Ok(())
+    }
+}
+
+impl Host for ArielOSHost {}
+
+impl HostSpiDevice for ArielOSHost {
+    fn open(&mut self, name: String) -> Result<Resource<SpiDevice>, SpiError> {
+        self.spi_host.open(name)
+    }
+
+    async fn transfer(
+        &mut self,
+        self_: Resource<SpiDevice>,
+        data: Vec<u8>,
+    ) -> Result<Vec<u8>, SpiError> {
+        self.before_deadline(
+            |host| host.spi_host.transfer(self_, data),
+            Err(SpiError::TimedOut),
+        )
+        .await
+    }
+
+    async fn write(&mut self, self_: Resource<SpiDevice>, data: Vec<u8>) -> Result<(), SpiError> {
+        self.before_deadline(
+            |host| host.spi_host.write(self_, data),
+            Err(SpiError::TimedOut),
+        )
+        .await
+    }
+
+    async fn read(&mut self, self_: Resource<SpiDevice>, len: u32) -> Result<Vec<u8>, SpiError> {
+        self.before_deadline(
+            |host| host.spi_host.read(self_, len),
+            Err(SpiError::TimedOut),
+        )
+        .await
+    }
+
+    async fn transaction(
+        &mut self,
+        self_: Resource<SpiDevice>,
+        operations: Vec<gen_spi::Operation>,
+    ) -> Result<Vec<Vec<u8>>, SpiError> {
+        self.before_deadline(
+            |host| host.spi_host.transaction(self_, operations),
+            Err(SpiError::TimedOut),
+        )
+        .await
+    }
+
+    fn drop(&mut self, rep: Resource<SpiDevice>) -> wasmtime::Result<()> {
+        self.spi_host.drop(rep)
+    }
+}
+
+impl ArielOSHost {
+    /// Grants the capsule access to `device`, which it can open under `name`.
+    ///
+    /// Binding a device under an already granted name replaces the previous device.
+    pub fn bind_spi_device(&mut self, name: &str, device: ArielSpiDevice) {
+        match self
+            .spi_host
+            .devices
+            .iter_mut()
+            .find(|(n, _)| n.as_str() == name)
+        {
+            Some((_, d)) => *d = device,
+            None => self.spi_host.devices.push((String::from(name), device)),
+        }
+    }
+    /// Sets how many bytes a capsule may read from a device in a single call.
+    pub fn set_spi_max_read_len(&mut self, len: usize) {
+        self.spi_host.max_read_len = len;
+    }
+}
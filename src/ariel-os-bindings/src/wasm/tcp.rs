@@ -0,0 +1,387 @@
+use ariel_os_embassy::api::time::Duration;
+use ariel_os_embassy::reexports::embassy_net;
+
+use embassy_net::IpEndpoint;
+use embassy_net::tcp::{AcceptError, ConnectError, Error as TcpIoError, TcpSocket};
+
+use wasmtime::component::{Resource, bindgen};
+
+extern crate alloc;
+use alloc::vec::Vec;
+
+use super::ArielOSHost;
+use super::ip_types::{Endpoint, UnsupportedAddress};
+use super::local::Local;
+use super::net_policy::{NetPolicy, PolicyError};
+
+bindgen!({
+    world: "ariel:wasm-bindings/tcp",
+    path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+    },
+    imports: {
+        "ariel:wasm-bindings/tcp-api.[static]tcp-stream.connect": async,
+        "ariel:wasm-bindings/tcp-api.[method]tcp-listener.accept": async,
+        "ariel:wasm-bindings/tcp-api.[method]tcp-stream.read": async,
+        "ariel:wasm-bindings/tcp-api.[method]tcp-stream.write": async,
+        "ariel:wasm-bindings/tcp-api.[method]tcp-stream.flush": async,
+    }
+});
+
+pub use ariel::wasm_bindings::tcp_api::{
+    self as gen_tcp, Host, HostTcpListener, HostTcpStream, HostWithStore, TcpError, TcpListener,
+    TcpStream, add_to_linker,
+};
+
+/// Buffers of a single TCP socket.
+///
+/// They have to outlive the store the capsule runs in, so they are meant to be placed in a
+/// `static`, e.g. with `static_cell::ConstStaticCell`.
+pub struct TcpSocketBuffers<const SIZE: usize> {
+    rx_buffer: [u8; SIZE],
+    tx_buffer: [u8; SIZE],
+}
+
+impl<const SIZE: usize> TcpSocketBuffers<SIZE> {
+    /// Buffers of `SIZE` bytes in each direction.
+    pub const fn new() -> Self {
+        Self {
+            rx_buffer: [0; SIZE],
+            tx_buffer: [0; SIZE],
+        }
+    }
+}
+
+impl<const SIZE: usize> Default for TcpSocketBuffers<SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// TCP sockets and listeners of a capsule.
+///
+/// Like for UDP, the sockets made from the buffers the host provides are kept in `free` while
+/// unused. The representation of a [`TcpStream`] resource is the index of the socket in
+/// `streams`, the one of a [`TcpListener`] its index in `listeners`, which hold the ports.
+#[derive(Default)]
+pub(crate) struct ArielTcpHost {
+    free: Vec<Local<TcpSocket<'static>>>,
+    streams: Vec<Option<Local<TcpSocket<'static>>>>,
+    listeners: Vec<Option<u16>>,
+    pub(crate) policy: NetPolicy,
+}
+
+/// Stores `value` in the first free slot of `slots` and returns its index.
+fn insert<T>(slots: &mut Vec<Option<T>>, value: T) -> u32 {
+    let index = match slots.iter().position(Option::is_none) {
+        Some(index) => {
+            slots[index] = Some(value);
+            index
+        }
+        None => {
+            slots.push(Some(value));
+            slots.len() - 1
+        }
+    };
+    index as u32
+}
+
+impl ArielTcpHost {
+    fn stream(&mut self, handle: &Resource<TcpStream>) -> &mut Local<TcpSocket<'static>> {
+        // Handles only come from `connect` and `accept` and are removed on drop
+        self.streams[handle.rep() as usize]
+            .as_mut()
+            .expect("Stream used after being dropped")
+    }
+
+    /// Socket that `connect` and `accept` wait on.
+    ///
+    /// It stays in the pool until it is connected, so a wait cut short by the deadline of the
+    /// capsule doesn't lose it. It is aborted first in case such a wait left it half-open.
+    fn waiting_socket(&mut self) -> Result<&mut Local<TcpSocket<'static>>, TcpError> {
+        let socket = self.free.last_mut().ok_or(TcpError::NoBuffers)?;
+        socket.abort();
+        Ok(socket)
+    }
+
+    /// Moves the socket returned by [`Self::waiting_socket`] to the streams once connected.
+    fn take_connected(&mut self) -> u32 {
+        let socket = self.free.pop().expect("Connected socket is in the pool");
+        insert(&mut self.streams, socket)
+    }
+
+    /// Returns a socket to the pool.
+    fn recycle(&mut self, mut socket: Local<TcpSocket<'static>>) {
+        socket.abort();
+        self.free.push(socket);
+    }
+
+    /// Takes a socket out of the pool for a protocol layered on TCP.
+    #[cfg(any(feature = "mqtt", feature = "http-client"))]
+    pub(crate) fn lease(&mut self) -> Result<Local<TcpSocket<'static>>, TcpError> {
+        self.free.pop().ok_or(TcpError::NoBuffers)
+    }
+
+    /// Returns a socket taken with [`Self::lease`] to the pool.
+    #[cfg(any(feature = "mqtt", feature = "http-client"))]
+    pub(crate) fn release(&mut self, socket: Local<TcpSocket<'static>>) {
+        self.recycle(socket);
+    }
+}
+
+impl From<PolicyError> for TcpError {
+    fn from(value: PolicyError) -> Self {
+        match value {
+            PolicyError::Denied => TcpError::PermissionDenied,
+            PolicyError::RateLimited => TcpError::RateLimited,
+        }
+    }
+}
+
+impl From<UnsupportedAddress> for TcpError {
+    fn from(_: UnsupportedAddress) -> Self {
+        TcpError::UnsupportedAddress
+    }
+}
+
+impl From<ConnectError> for TcpError {
+    fn from(value: ConnectError) -> Self {
+        match value {
+            ConnectError::InvalidState => TcpError::InvalidState,
+            ConnectError::ConnectionReset => TcpError::ConnectionReset,
+            ConnectError::TimedOut => TcpError::TimedOut,
+            ConnectError::NoRoute => TcpError::NoRoute,
+        }
+    }
+}
+
+impl From<AcceptError> for TcpError {
+    fn from(value: AcceptError) -> Self {
+        match value {
+            AcceptError::InvalidState => TcpError::InvalidState,
+            AcceptError::InvalidPort => TcpError::InvalidPort,
+            AcceptError::ConnectionReset => TcpError::ConnectionReset,
+        }
+    }
+}
+
+impl From<TcpIoError> for TcpError {
+    fn from(_: TcpIoError) -> Self {
+        TcpError::ConnectionReset
+    }
+}
+
+impl Host for ArielTcpHost {}
+
+impl HostTcpListener for ArielTcpHost {
+    fn listen(&mut self, port: u16) -> Result<Resource<TcpListener>, TcpError> {
+        // Accepting on a port shared by two listeners would hand connections to either
+        if port == 0 || self.listeners.contains(&Some(port)) {
+            return Err(TcpError::InvalidPort);
+        }
+        self.policy.check_local_port(port)?;
+        Ok(Resource::new_own(insert(&mut self.listeners, port)))
+    }
+
+    async fn accept(
+        &mut self,
+        self_: Resource<TcpListener>,
+    ) -> Result<Resource<TcpStream>, TcpError> {
+        let port = self.listeners[self_.rep() as usize].expect("Listener used after being dropped");
+        let socket = self.waiting_socket()?;
+        if let Err(err) = socket.run(|socket| socket.accept(port)).await {
+            socket.abort();
+            return Err(err.into());
+        }
+        Ok(Resource::new_own(self.take_connected()))
+    }
+
+    fn drop(&mut self, rep: Resource<TcpListener>) -> wasmtime::Result<()> {
+        if let Some(slot) = self.listeners.get_mut(rep.rep() as usize) {
+            *slot = None;
+        }
+        Ok(())
+    }
+}
+
+impl HostTcpStream for ArielTcpHost {
+    async fn connect(&mut self, remote: Endpoint) -> Result<Resource<TcpStream>, TcpError> {
+        let remote = IpEndpoint::try_from(remote)?;
+        self.policy.check_remote(&remote)?;
+        let socket = self.waiting_socket()?;
+        if let Err(err) = socket.run(|socket| socket.connect(remote)).await {
+            socket.abort();
+            return Err(err.into());
+        }
+        Ok(Resource::new_own(self.take_connected()))
+    }
+
+    async fn read(
+        &mut self,
+        self_: Resource<TcpStream>,
+        max_len: u32,
+    ) -> Result<Vec<u8>, TcpError> {
+        if max_len == 0 {
+            return Err(TcpError::InvalidArgument);
+        }
+        let socket = self.stream(&self_);
+        let len = (max_len as usize).min(socket.recv_capacity());
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, len).collect();
+        let n = socket.run(|socket| socket.read(&mut buf)).await?;
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    async fn write(&mut self, self_: Resource<TcpStream>, data: Vec<u8>) -> Result<u32, TcpError> {
+        let policy = &mut self.policy;
+        let socket = self.streams[self_.rep() as usize]
+            .as_mut()
+            .expect("Stream used after being dropped");
+        // Only the bytes the socket queues are accounted for
+        let written = socket
+            .run(|socket| {
+                socket.write_with(move |buf| {
+                    let n = buf.len().min(data.len());
+                    match policy.consume(n) {
+                        Ok(()) => {
+                            buf[..n].copy_from_slice(&data[..n]);
+                            (n, Ok(n))
+                        }
+                        Err(err) => (0, Err(err)),
+                    }
+                })
+            })
+            .await?;
+        Ok(written? as u32)
+    }
+
+    async fn flush(&mut self, self_: Resource<TcpStream>) -> Result<(), TcpError> {
+        Ok(self.stream(&self_).run(|socket| socket.flush()).await?)
+    }
+
+    fn shutdown(&mut self, self_: Resource<TcpStream>) {
+        self.stream(&self_).close();
+    }
+
+    fn remote_endpoint(&mut self, self_: Resource<TcpStream>) -> Option<Endpoint> {
+        self.stream(&self_)
+            .remote_endpoint()
+            .and_then(|endpoint| endpoint.try_into().ok())
+    }
+
+    fn set_timeout(&mut self, self_: Resource<TcpStream>, millis: Option<u64>) {
+        self.stream(&self_)
+            .set_timeout(millis.map(Duration::from_millis));
+    }
+
+    fn drop(&mut self, rep: Resource<TcpStream>) -> wasmtime::Result<()> {
+        if let Some(socket) = self
+            .streams
+            .get_mut(rep.rep() as usize)
+            .and_then(Option::take)
+        {
+            self.recycle(socket);
+        }
+        Ok(())
+    }
+}
+
+impl Host for ArielOSHost {}
+
+impl HostTcpListener for ArielOSHost {
+    fn listen(&mut self, port: u16) -> Result<Resource<TcpListener>, TcpError> {
+        self.tcp_host.listen(port)
+    }
+
+    async fn accept(
+        &mut self,
+        self_: Resource<TcpListener>,
+    ) -> Result<Resource<TcpStream>, TcpError> {
+        self.before_deadline(|host| host.tcp_host.accept(self_), Err(TcpError::TimedOut))
+            .await
+    }
+
+    fn drop(&mut self, rep: Resource<TcpListener>) -> wasmtime::Result<()> {
+        HostTcpListener::drop(&mut self.tcp_host, rep)
+    }
+}
+
+impl HostTcpStream for ArielOSHost {
+    async fn connect(&mut self, remote: Endpoint) -> Result<Resource<TcpStream>, TcpError> {
+        self.before_deadline(
+            |host| host.tcp_host.connect(remote),
+            Err(TcpError::TimedOut),
+        )
+        .await
+    }
+
+    async fn read(
+        &mut self,
+        self_: Resource<TcpStream>,
+        max_len: u32,
+    ) -> Result<Vec<u8>, TcpError> {
+        self.before_deadline(
+            |host| host.tcp_host.read(self_, max_len),
+            Err(TcpError::TimedOut),
+        )
+        .await
+    }
+
+    async fn write(&mut self, self_: Resource<TcpStream>, data: Vec<u8>) -> Result<u32, TcpError> {
+        self.before_deadline(
+            |host| host.tcp_host.write(self_, data),
+            Err(TcpError::TimedOut),
+        )
+        .await
+    }
+
+    async fn flush(&mut self, self_: Resource<TcpStream>) -> Result<(), TcpError> {
+        self.before_deadline(|host| host.tcp_host.flush(self_), Err(TcpError::TimedOut))
+            .await
+    }
+
+    fn shutdown(&mut self, self_: Resource<TcpStream>) {
+        self.tcp_host.shutdown(self_)
+    }
+
+    fn remote_endpoint(&mut self, self_: Resource<TcpStream>) -> Option<Endpoint> {
+        self.tcp_host.remote_endpoint(self_)
+    }
+
+    fn set_timeout(&mut self, self_: Resource<TcpStream>, millis: Option<u64>) {
+        self.tcp_host.set_timeout(self_, millis)
+    }
+
+    fn drop(&mut self, rep: Resource<TcpStream>) -> wasmtime::Result<()> {
+        HostTcpStream::drop(&mut self.tcp_host, rep)
+    }
+}
+
+impl ArielOSHost {
+    /// Lets the capsule have one more TCP connection, which uses `buffers`.
+    ///
+    /// # Safety
+    ///
+    /// The host must only be used, and dropped, on the executor running `stack`, which owns
+    /// the state of the socket.
+    pub unsafe fn add_tcp_socket<const SIZE: usize>(
+        &mut self,
+        stack: ariel_os_embassy::NetworkStack,
+        buffers: &'static mut TcpSocketBuffers<SIZE>,
+    ) {
+        let TcpSocketBuffers {
+            rx_buffer,
+            tx_buffer,
+        } = buffers;
+        let socket = TcpSocket::new(stack, rx_buffer, tx_buffer);
+        // SAFETY: Required from the caller
+        self.tcp_host.free.push(unsafe { Local::new(socket) });
+    }
+
+    /// Restricts the ports the capsule may listen on, the endpoints it may connect to and the
+    /// rate at which it writes.
+    pub fn set_tcp_policy(&mut self, policy: NetPolicy) {
+        self.tcp_host.policy = policy;
+    }
+}
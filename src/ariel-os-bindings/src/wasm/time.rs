@@ -1,6 +1,9 @@
-use wasmtime::component::bindgen;
+extern crate alloc;
+use alloc::vec::Vec;
 
-use ariel_os_embassy::api::time::{Instant, Timer};
+use wasmtime::component::{Resource, bindgen};
+
+use ariel_os_embassy::api::time::{Duration, Instant, Timer};
 
 use super::ArielOSHost;
 
@@ -10,17 +13,217 @@ bindgen!({
 
     imports: {
         "ariel:wasm-bindings/time-api.sleep": async,
+        "ariel:wasm-bindings/time-api.sleep-until": async,
+        "ariel:wasm-bindings/time-api.[method]timer.wait": async,
     }
 });
 
-pub use ariel::wasm_bindings::time_api::{Host, HostWithStore, add_to_linker};
+pub use ariel::wasm_bindings::time_api::{
+    self as gen_time, Host, HostTimer, HostWithStore, add_to_linker,
+};
+
+struct TimerState {
+    period: Duration,
+    next: Instant,
+}
+
+/// Time related state of a capsule.
+///
+/// The representation of a [`gen_time::Timer`] resource is its index in `timers`.
+///
+/// In virtual time mode, the time seen by the capsule is `virtual_now`, which only moves forward
+/// when the capsule sleeps or waits, and waiting completes immediately.
+#[derive(Default)]
+pub(crate) struct ArielTimeHost {
+    /// Always in real time, so waits on other peripherals can be bounded by it.
+    pub(crate) deadline: Option<Instant>,
+    timers: Vec<Option<TimerState>>,
+    virtual_now: Option<Instant>,
+}
+
+/// Runs `fut` until it completes or `deadline` passes, in which case it returns `expired`.
+#[cfg(any(
+    feature = "gpio",
+    feature = "sensors-async",
+    feature = "i2c",
+    feature = "spi",
+    feature = "uart",
+    feature = "udp",
+    feature = "tcp",
+    feature = "dns",
+    feature = "net-info",
+))]
+pub(crate) async fn before_deadline<T>(
+    deadline: Option<Instant>,
+    fut: impl Future<Output = T>,
+    expired: T,
+) -> T {
+    use embassy_futures::select::{Either, select};
+
+    match deadline {
+        Some(deadline) => match select(fut, Timer::at(deadline)).await {
+            Either::First(result) => result,
+            Either::Second(()) => expired,
+        },
+        None => fut.await,
+    }
+}
+
+/// Adds `duration` to `at`, saturating to [`Instant::MAX`] when either doesn't fit.
+fn saturating_after(at: Instant, duration: Option<Duration>) -> Instant {
+    duration
+        .and_then(|duration| at.checked_add(duration))
+        .unwrap_or(Instant::MAX)
+}
+
+fn saturating_instant(micros: u64) -> Instant {
+    Instant::try_from_micros(micros).unwrap_or(Instant::MAX)
+}
+
+impl ArielTimeHost {
+    fn now(&self) -> Instant {
+        self.virtual_now.unwrap_or_else(Instant::now)
+    }
+
+    async fn wait_until(&mut self, at: Instant) {
+        match &mut self.virtual_now {
+            Some(now) => {
+                *now = (*now).max(at);
+                // Still give other tasks a chance to run
+                embassy_futures::yield_now().await;
+            }
+            None => Timer::at(at).await,
+        }
+    }
+
+    fn timer(&mut self, handle: &Resource<gen_time::Timer>) -> &mut TimerState {
+        // Handles only come from `every` and are removed on drop
+        self.timers[handle.rep() as usize]
+            .as_mut()
+            .expect("Timer used after being dropped")
+    }
+}
+
+impl Host for ArielTimeHost {
+    async fn sleep(&mut self, millis: u64) {
+        let at = saturating_after(self.now(), Duration::try_from_millis(millis));
+        self.wait_until(at).await;
+    }
+
+    fn now_as_millis(&mut self) -> u64 {
+        self.now().as_millis()
+    }
+
+    fn now_as_micros(&mut self) -> u64 {
+        self.now().as_micros()
+    }
+
+    async fn sleep_until(&mut self, micros: u64) {
+        self.wait_until(saturating_instant(micros)).await;
+    }
+
+    fn set_deadline(&mut self, micros: Option<u64>) {
+        self.deadline = micros.map(|micros| match self.virtual_now {
+            // Grant the remaining virtual time as real time
+            Some(now) => saturating_after(
+                Instant::now(),
+                Duration::try_from_micros(micros.saturating_sub(now.as_micros())),
+            ),
+            None => saturating_instant(micros),
+        });
+    }
+}
+
+impl HostTimer for ArielTimeHost {
+    fn every(&mut self, period_micros: u64) -> Result<Resource<gen_time::Timer>, ()> {
+        if period_micros == 0 {
+            return Err(());
+        }
+        let period = Duration::try_from_micros(period_micros).unwrap_or(Duration::MAX);
+        let state = TimerState {
+            period,
+            next: saturating_after(self.now(), Some(period)),
+        };
+        let index = match self.timers.iter().position(Option::is_none) {
+            Some(index) => {
+                self.timers[index] = Some(state);
+                index
+            }
+            None => {
+                self.timers.push(Some(state));
+                self.timers.len() - 1
+            }
+        };
+        Ok(Resource::new_own(index as u32))
+    }
+
+    async fn wait(&mut self, self_: Resource<gen_time::Timer>) {
+        let next = self.timer(&self_).next;
+        self.wait_until(next).await;
+        let timer = self.timer(&self_);
+        timer.next = saturating_after(next, Some(timer.period));
+    }
+
+    fn reset(&mut self, self_: Resource<gen_time::Timer>) {
+        let now = self.now();
+        let timer = self.timer(&self_);
+        timer.next = saturating_after(now, Some(timer.period));
+    }
+
+    fn drop(&mut self, rep: Resource<gen_time::Timer>) -> wasmtime::Result<()> {
+        if let Some(slot) = self.timers.get_mut(rep.rep() as usize) {
+            *slot = None;
+        }
+        Ok(())
+    }
+}
 
 impl Host for ArielOSHost {
     async fn sleep(&mut self, millis: u64) {
-        Timer::after_millis(millis).await;
+        self.time_host.sleep(millis).await
     }
 
     fn now_as_millis(&mut self) -> u64 {
-        Instant::now().as_millis()
+        self.time_host.now_as_millis()
+    }
+
+    fn now_as_micros(&mut self) -> u64 {
+        self.time_host.now_as_micros()
+    }
+
+    async fn sleep_until(&mut self, micros: u64) {
+        self.time_host.sleep_until(micros).await
+    }
+
+    fn set_deadline(&mut self, micros: Option<u64>) {
+        self.time_host.set_deadline(micros)
+    }
+}
+
+impl HostTimer for ArielOSHost {
+    fn every(&mut self, period_micros: u64) -> Result<Resource<gen_time::Timer>, ()> {
+        self.time_host.every(period_micros)
+    }
+
+    async fn wait(&mut self, self_: Resource<gen_time::Timer>) {
+        self.time_host.wait(self_).await
+    }
+
+    fn reset(&mut self, self_: Resource<gen_time::Timer>) {
+        self.time_host.reset(self_)
+    }
+
+    fn drop(&mut self, rep: Resource<gen_time::Timer>) -> wasmtime::Result<()> {
+        self.time_host.drop(rep)
+    }
+}
+
+impl ArielOSHost {
+    /// Switches the capsule to virtual time starting at `start_micros`.
+    ///
+    /// Sleeps and timers then complete immediately and only advance the time the capsule sees, so
+    /// runs don't depend on the host's timing.
+    pub fn use_virtual_time(&mut self, start_micros: u64) {
+        self.time_host.virtual_now = Some(saturating_instant(start_micros));
     }
 }
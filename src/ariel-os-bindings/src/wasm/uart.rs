@@ -0,0 +1,191 @@
+extern crate alloc;
+use alloc::vec::Vec;
+
+use embassy_futures::select::{Either, select};
+use embassy_sync::blocking_mutex::raw::CriticalSectionRawMutex;
+use embassy_sync::pipe::Pipe;
+use embassy_sync::signal::Signal;
+
+use embedded_io_async::{Read, Write};
+
+use wasmtime::component::bindgen;
+
+use super::ArielOSHost;
+
+bindgen!({
+    world: "ariel:wasm-bindings/uart",
+    path: "../../wit/",
+    imports: {
+        "ariel:wasm-bindings/uart-api.read": async,
+        "ariel:wasm-bindings/uart-api.write": async,
+    }
+});
+
+pub use ariel::wasm_bindings::uart_api::{
+    self as gen_uart, DataBits, Host, HostWithStore, LineConfig, Parity, StopBits, add_to_linker,
+};
+
+use gen_uart::UartError;
+
+/// Size of the receive and of the transmit buffers of a [`UartBridge`].
+pub const UART_BUFFER_SIZE: usize = 256;
+
+/// Buffers between a UART driven by the firmware and a capsule.
+///
+/// The firmware runs [`UartBridge::run_uart`] on an Ariel OS UART (or [`UartBridge::run`] on its
+/// two halves) and applies the line settings obtained from [`UartBridge::wait_for_config`], the
+/// capsule only ever touches the buffers.
+pub struct UartBridge {
+    rx: Pipe<CriticalSectionRawMutex, UART_BUFFER_SIZE>,
+    tx: Pipe<CriticalSectionRawMutex, UART_BUFFER_SIZE>,
+    config: Signal<CriticalSectionRawMutex, LineConfig>,
+}
+
+impl UartBridge {
+    pub const fn new() -> Self {
+        Self {
+            rx: Pipe::new(),
+            tx: Pipe::new(),
+            config: Signal::new(),
+        }
+    }
+
+    /// Moves data between the two halves of a UART and the buffers, only returns once either
+    /// half errors.
+    ///
+    /// When the receive buffer is full because the capsule doesn't read, reading from the UART
+    /// stops until there is room again.
+    pub async fn run<R: Read, W: Write>(&self, mut rx: R, mut tx: W) {
+        let receive = async {
+            let mut buf = [0; 32];
+            loop {
+                match rx.read(&mut buf).await {
+                    Ok(n) => self.rx.write_all(&buf[..n]).await,
+                    Err(_) => return,
+                }
+            }
+        };
+        let transmit = async {
+            let mut buf = [0; 32];
+            loop {
+                let n = self.tx.read(&mut buf).await;
+                if tx.write_all(&buf[..n]).await.is_err() {
+                    return;
+                }
+            }
+        };
+        // The firmware is expected to restart the bridge after handling the error
+        select(receive, transmit).await;
+    }
+
+    /// Moves data between a UART that can't be split, like the drivers of Ariel OS, and the
+    /// buffers, only returns if the UART errors.
+    ///
+    /// Reads from `uart` are interrupted to send what the capsule wrote, which the buffered
+    /// drivers of Ariel OS allow without losing data. While the receive buffer is full, nothing
+    /// is sent either.
+    pub async fn run_uart<U: Read + Write>(&self, mut uart: U) {
+        let mut rx_buf = [0; 32];
+        let mut tx_buf = [0; 32];
+        loop {
+            match select(uart.read(&mut rx_buf), self.tx.read(&mut tx_buf)).await {
+                Either::First(Ok(n)) => self.rx.write_all(&rx_buf[..n]).await,
+                Either::First(Err(_)) => return,
+                Either::Second(n) => {
+                    if uart.write_all(&tx_buf[..n]).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Waits for the capsule to request new line settings.
+    pub async fn wait_for_config(&self) -> LineConfig {
+        self.config.wait().await
+    }
+}
+
+impl Default for UartBridge {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ArielUartHost {
+    pub(crate) bridge: Option<&'static UartBridge>,
+    pub(crate) max_baudrate: u32,
+}
+
+impl Host for ArielUartHost {
+    fn set_config(&mut self, config: LineConfig) -> Result<(), UartError> {
+        let bridge = self.bridge.ok_or(UartError::NotBound)?;
+        if config.baudrate == 0 || config.baudrate > self.max_baudrate {
+            return Err(UartError::UnsupportedConfig);
+        }
+        bridge.config.signal(config);
+        Ok(())
+    }
+
+    async fn read(&mut self, max_len: u32) -> Result<Vec<u8>, UartError> {
+        let bridge = self.bridge.ok_or(UartError::NotBound)?;
+        // Never more than the receive buffer holds
+        let len = (max_len as usize).min(UART_BUFFER_SIZE);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, len).collect();
+        if !buf.is_empty() {
+            let n = bridge.rx.read(&mut buf).await;
+            buf.truncate(n);
+        }
+        Ok(buf)
+    }
+
+    fn try_read(&mut self, max_len: u32) -> Result<Vec<u8>, UartError> {
+        let bridge = self.bridge.ok_or(UartError::NotBound)?;
+        let len = (max_len as usize).min(UART_BUFFER_SIZE);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, len).collect();
+        // An empty pipe is not an error, just nothing to return
+        let n = bridge.rx.try_read(&mut buf).unwrap_or(0);
+        buf.truncate(n);
+        Ok(buf)
+    }
+
+    async fn write(&mut self, data: Vec<u8>) -> Result<(), UartError> {
+        let bridge = self.bridge.ok_or(UartError::NotBound)?;
+        bridge.tx.write_all(&data).await;
+        Ok(())
+    }
+}
+
+impl Host for ArielOSHost {
+    fn set_config(&mut self, config: LineConfig) -> Result<(), UartError> {
+        self.uart_host.set_config(config)
+    }
+
+    async fn read(&mut self, max_len: u32) -> Result<Vec<u8>, UartError> {
+        self.before_deadline(
+            |host| host.uart_host.read(max_len),
+            Err(UartError::TimedOut),
+        )
+        .await
+    }
+
+    fn try_read(&mut self, max_len: u32) -> Result<Vec<u8>, UartError> {
+        self.uart_host.try_read(max_len)
+    }
+
+    async fn write(&mut self, data: Vec<u8>) -> Result<(), UartError> {
+        self.before_deadline(|host| host.uart_host.write(data), Err(UartError::TimedOut))
+            .await
+    }
+}
+
+impl ArielOSHost {
+    /// Connects the capsule to a UART through `bridge`.
+    ///
+    /// Line settings with a baudrate above `max_baudrate` are refused.
+    pub fn bind_uart(&mut self, bridge: &'static UartBridge, max_baudrate: u32) {
+        self.uart_host.bridge = Some(bridge);
+        self.uart_host.max_baudrate = max_baudrate;
+    }
+}
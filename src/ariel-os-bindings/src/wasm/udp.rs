@@ -1,12 +1,18 @@
 use ariel_os_debug::log::info;
 use ariel_os_embassy::reexports::embassy_net;
 
-use embassy_net::IpAddress;
-use embassy_net::udp::{PacketMetadata, UdpMetadata, UdpSocket};
+use embassy_net::udp::{BindError, PacketMetadata, RecvError, SendError, UdpMetadata, UdpSocket};
+use embassy_net::{IpAddress, IpEndpoint};
 
 use wasmtime::component::Resource;
 
-use core::mem;
+use core::task::{Context, Poll, Waker};
+
+#[cfg(feature = "multicast")]
+use core::cell::RefCell;
+
+#[cfg(feature = "multicast")]
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
 
 extern crate alloc;
 use alloc::vec::Vec;
@@ -14,194 +20,430 @@ use alloc::vec::Vec;
 use wasmtime::component::bindgen;
 
 use super::ArielOSHost;
+use super::ip_types::{IpAddr, UnsupportedAddress};
+use super::local::Local;
+use super::net_policy::{NetPolicy, PolicyError};
 
 bindgen!({
     world: "ariel:wasm-bindings/udp",
     path: "../../wit/",
+    with: {
+        "ariel:wasm-bindings/ip-types": super::ip_types,
+    },
+    imports: {
+        "ariel:wasm-bindings/udp-api.[method]udp-socket.send": async,
+        "ariel:wasm-bindings/udp-api.[method]udp-socket.recv": async,
+    }
 });
 
 pub use ariel::wasm_bindings::udp_api::add_to_linker;
 pub use ariel::wasm_bindings::udp_api::{self as gen_udp, Host, HostUdpSocket, HostWithStore};
 
-use embassy_futures::block_on;
+use gen_udp::UdpError;
+
+/// Buffers of a single UDP socket.
+///
+/// They have to outlive the store the capsule runs in, so they are meant to be placed in a
+/// `static`, e.g. with `static_cell::ConstStaticCell`.
+pub struct UdpSocketBuffers<const PACKETS: usize, const SIZE: usize> {
+    rx_meta: [PacketMetadata; PACKETS],
+    rx_buffer: [u8; SIZE],
+    tx_meta: [PacketMetadata; PACKETS],
+    tx_buffer: [u8; SIZE],
+}
+
+impl<const PACKETS: usize, const SIZE: usize> UdpSocketBuffers<PACKETS, SIZE> {
+    /// Buffers for up to `PACKETS` packets and `SIZE` bytes in each direction.
+    pub const fn new() -> Self {
+        Self {
+            rx_meta: [PacketMetadata::EMPTY; PACKETS],
+            rx_buffer: [0; SIZE],
+            tx_meta: [PacketMetadata::EMPTY; PACKETS],
+            tx_buffer: [0; SIZE],
+        }
+    }
+}
+
+impl<const PACKETS: usize, const SIZE: usize> Default for UdpSocketBuffers<PACKETS, SIZE> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Number of sockets, of any capsule, that are members of each multicast group the interface
+/// joined.
+#[cfg(feature = "multicast")]
+static GROUP_MEMBERS: Mutex<CriticalSectionRawMutex, RefCell<Vec<(IpAddress, usize)>>> =
+    Mutex::new(RefCell::new(Vec::new()));
+
+/// Counts one more member of `group`, joining it on the interface for the first one.
+#[cfg(feature = "multicast")]
+fn acquire_group(stack: ariel_os_embassy::NetworkStack, group: IpAddress) -> Result<(), UdpError> {
+    GROUP_MEMBERS.lock(|members| {
+        let mut members = members.borrow_mut();
+        match members.iter_mut().find(|(g, _)| *g == group) {
+            Some((_, count)) => *count += 1,
+            None => {
+                stack
+                    .join_multicast_group(group)
+                    .map_err(|_| UdpError::TooManyGroups)?;
+                members.push((group, 1));
+            }
+        }
+        Ok(())
+    })
+}
+
+/// Counts one member of `group` less, leaving it on the interface after the last one.
+#[cfg(feature = "multicast")]
+fn release_group(stack: ariel_os_embassy::NetworkStack, group: IpAddress) {
+    GROUP_MEMBERS.lock(|members| {
+        let mut members = members.borrow_mut();
+        if let Some(index) = members.iter().position(|(g, _)| *g == group) {
+            members[index].1 -= 1;
+            if members[index].1 == 0 {
+                members.swap_remove(index);
+                let _ = stack.leave_multicast_group(group);
+            }
+        }
+    });
+}
+
+/// A socket and the size of its receive buffer.
+pub(crate) struct PooledSocket {
+    pub(crate) socket: Local<UdpSocket<'static>>,
+    pub(crate) capacity: usize,
+    /// Multicast groups joined through this socket.
+    #[cfg(feature = "multicast")]
+    groups: Vec<IpAddress>,
+}
 
+/// UDP sockets of a capsule.
+///
+/// The host provides the buffers of every socket the capsule may open at once, the sockets made
+/// from them are kept in `free` while unused. The representation of a [`gen_udp::UdpSocket`]
+/// resource is the index of the socket in `sockets`.
 #[derive(Default)]
 pub struct ArielUDPHost {
-    socket: Option<UdpSocket<'static>>,
-    buffer_size: usize,
+    free: Vec<PooledSocket>,
+    sockets: Vec<Option<PooledSocket>>,
+    pub(crate) policy: NetPolicy,
+    #[cfg(feature = "multicast")]
+    stack: Option<Local<ariel_os_embassy::NetworkStack>>,
+    /// Multicast groups the capsule may join.
+    #[cfg(feature = "multicast")]
+    allowed_groups: Vec<IpAddress>,
 }
 
 impl ArielUDPHost {
-    pub unsafe fn initialize_socket(
+    /// # Safety
+    ///
+    /// See [`ArielOSHost::add_udp_buffers`].
+    pub unsafe fn add_buffers(
         &mut self,
         stack: ariel_os_embassy::NetworkStack,
-        rx_meta: &mut [PacketMetadata],
-        rx_buffer: &mut [u8],
-        tx_meta: &mut [PacketMetadata],
-        tx_buffer: &mut [u8],
+        rx_meta: &'static mut [PacketMetadata],
+        rx_buffer: &'static mut [u8],
+        tx_meta: &'static mut [PacketMetadata],
+        tx_buffer: &'static mut [u8],
     ) {
-        let buff_size = tx_buffer.len();
-        let rx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(rx_meta) };
-        let rx_buffer: &'static mut [u8] = unsafe { mem::transmute(rx_buffer) };
-        let tx_meta: &'static mut [PacketMetadata] = unsafe { mem::transmute(tx_meta) };
-        let tx_buffer: &'static mut [u8] = unsafe { mem::transmute(tx_buffer) };
-
+        let capacity = rx_buffer.len();
         let socket = UdpSocket::new(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        self.free.push(PooledSocket {
+            // SAFETY: Required from the caller
+            socket: unsafe { Local::new(socket) },
+            capacity,
+            #[cfg(feature = "multicast")]
+            groups: Vec::new(),
+        });
+        #[cfg(feature = "multicast")]
+        {
+            // SAFETY: Required from the caller
+            self.stack = Some(unsafe { Local::new(stack) });
+        }
+    }
+
+    /// Takes a socket out of the pool for a protocol layered on UDP, bound to an ephemeral port.
+    #[cfg(feature = "dtls")]
+    pub(crate) fn lease(&mut self) -> Result<PooledSocket, UdpError> {
+        let mut pooled = self.free.pop().ok_or(UdpError::NoBuffers)?;
+        // Port 0 lets the stack pick an ephemeral port
+        if let Err(err) = pooled.socket.bind(0) {
+            self.free.push(pooled);
+            return Err(err.into());
+        }
+        Ok(pooled)
+    }
+
+    /// Returns a socket taken with [`Self::lease`] to the pool.
+    #[cfg(feature = "dtls")]
+    pub(crate) fn release(&mut self, mut pooled: PooledSocket) {
+        pooled.socket.close();
+        self.free.push(pooled);
+    }
 
-        self.socket = Some(socket);
-        self.buffer_size = buff_size;
+    fn socket(&mut self, handle: &Resource<gen_udp::UdpSocket>) -> &mut PooledSocket {
+        // Handles only come from `bind` and are removed on drop
+        self.sockets[handle.rep() as usize]
+            .as_mut()
+            .expect("Socket used after being dropped")
     }
 }
 
-impl gen_udp::Ipv4Addr {
-    fn from_octets(octets: [u8; 4]) -> Self {
-        Self {
-            a: octets[0],
-            b: octets[1],
-            c: octets[2],
-            d: octets[3],
+impl From<PolicyError> for UdpError {
+    fn from(value: PolicyError) -> Self {
+        match value {
+            PolicyError::Denied => UdpError::PermissionDenied,
+            PolicyError::RateLimited => UdpError::RateLimited,
         }
     }
 }
 
-// #[cfg(feature = "ipv6")]
-// impl udp::Ipv6Addr {
-//     fn from_segments(segments: [u16; 8]) -> Self {
-//         Self {
-//             a: segments[0],
-//             b: segments[1],
-//             c: segments[2],
-//             d: segments[3],
-//             e: segments[4],
-//             f: segments[5],
-//             g: segments[6],
-//             h: segments[7],
-//         }
-//     }
-// }
-
-impl From<IpAddress> for gen_udp::IpAddr {
-    fn from(t: IpAddress) -> Self {
-        match t {
-            IpAddress::Ipv4(ipaddr) => {
-                let octs = ipaddr.octets();
-                gen_udp::IpAddr::V4(gen_udp::Ipv4Addr::from_octets(octs))
-            }
-            // #[cfg(feature = "ipv6")]
-            // IpAddress::Ipv6(ipaddr) => {
-            //     let segments = ipaddr.segments();
-            //     udp::IpAddr::V6(
-            //         udp::Ipv6Addr::from_segments(segments)
-            //     )
-            // },
-            #[allow(unreachable_patterns, reason = "Conditional compilation")]
-            _ => unreachable!(),
+impl From<BindError> for UdpError {
+    fn from(value: BindError) -> Self {
+        match value {
+            BindError::NoRoute => UdpError::NoRoute,
+            _ => UdpError::BindFailed,
         }
     }
 }
 
-impl From<gen_udp::IpAddr> for IpAddress {
-    fn from(t: gen_udp::IpAddr) -> Self {
-        match t {
-            gen_udp::IpAddr::V4(ipaddr) => {
-                let gen_udp::Ipv4Addr { a, b, c, d } = ipaddr;
-                Self::v4(a, b, c, d)
-            }
-            // #[cfg(feature = "ipv6")]
-            // udp::IpAddr::V6(ipaddr) => {
-            //     let udp::Ipv6Addr { a, b, c, d, e, f, g, h } = ipaddr;
-            //     Self::v6(a, b, c, d, e, f, g, h)
-            // },
-            #[allow(unreachable_patterns, reason = "Conditional compilation")]
-            _ => unreachable!(),
+impl From<SendError> for UdpError {
+    fn from(value: SendError) -> Self {
+        match value {
+            SendError::PacketTooLarge => UdpError::PacketTooLarge,
+            _ => UdpError::NoRoute,
         }
     }
 }
 
-impl From<UdpMetadata> for gen_udp::UdpMetadata {
-    fn from(t: UdpMetadata) -> Self {
+impl From<UnsupportedAddress> for UdpError {
+    fn from(_: UnsupportedAddress) -> Self {
+        UdpError::UnsupportedAddress
+    }
+}
+
+impl From<RecvError> for UdpError {
+    fn from(_: RecvError) -> Self {
+        UdpError::Truncated
+    }
+}
+
+/// Whether packets can be sent from `addr`, which is not the case of the multicast and broadcast
+/// addresses a packet may have been received on.
+fn is_unicast(addr: &IpAddress) -> bool {
+    match addr {
+        IpAddress::Ipv4(addr) => !addr.is_multicast() && !addr.is_broadcast(),
+        #[cfg(feature = "ipv6")]
+        IpAddress::Ipv6(addr) => !addr.is_multicast(),
+        #[allow(unreachable_patterns, reason = "Conditional compilation")]
+        _ => false,
+    }
+}
+
+impl TryFrom<UdpMetadata> for gen_udp::UdpMetadata {
+    type Error = UdpError;
+
+    fn try_from(t: UdpMetadata) -> Result<Self, UdpError> {
         let UdpMetadata {
             endpoint,
             local_address,
             meta: _,
         } = t;
-        let e_addr = endpoint.addr.into();
-        let e_port = endpoint.port;
-
-        Self {
-            endpoint: gen_udp::Endpoint {
-                addr: e_addr,
-                port: e_port,
-            },
-            local_addr: local_address.map(gen_udp::IpAddr::from),
-        }
+        Ok(Self {
+            endpoint: endpoint.try_into()?,
+            local_addr: local_address.and_then(|addr| addr.try_into().ok()),
+        })
     }
 }
 
-impl From<gen_udp::UdpMetadata> for UdpMetadata {
-    fn from(t: gen_udp::UdpMetadata) -> Self {
+impl TryFrom<gen_udp::UdpMetadata> for UdpMetadata {
+    type Error = UdpError;
+
+    /// The local address is kept when it is unicast, so that replies to a link-local peer go out
+    /// from the link-local address the request was received on.
+    fn try_from(t: gen_udp::UdpMetadata) -> Result<Self, UdpError> {
         let gen_udp::UdpMetadata {
             endpoint,
-            local_addr: _,
+            local_addr,
         } = t;
-        let e_addr: IpAddress = endpoint.addr.into();
-        let e_port = endpoint.port;
+        let endpoint = IpEndpoint::try_from(endpoint)?;
 
-        (e_addr, e_port).into()
+        let mut metadata: UdpMetadata = endpoint.into();
+        metadata.local_address = local_addr
+            .and_then(|addr| IpAddress::try_from(addr).ok())
+            .filter(is_unicast);
+        Ok(metadata)
     }
 }
 
 impl HostUdpSocket for ArielUDPHost {
-    fn bind(&mut self, port: u16) -> Result<(), ()> {
-        match self.socket.as_mut() {
-            Some(socket) => socket.bind(port).map_err(|_| ()),
+    fn bind(&mut self, port: u16) -> Result<Resource<gen_udp::UdpSocket>, UdpError> {
+        self.policy.check_local_port(port)?;
+        let mut pooled = self.free.pop().ok_or(UdpError::NoBuffers)?;
+        if let Err(err) = pooled.socket.bind(port) {
+            self.free.push(pooled);
+            return Err(err.into());
+        }
+        let index = match self.sockets.iter().position(Option::is_none) {
+            Some(index) => {
+                self.sockets[index] = Some(pooled);
+                index
+            }
             None => {
-                info!("Unintialized Socket");
-                Err(())
+                self.sockets.push(Some(pooled));
+                self.sockets.len() - 1
             }
-        }
+        };
+        Ok(Resource::new_own(index as u32))
     }
 
-    fn send(&mut self, data: Vec<u8>, endpoint: gen_udp::UdpMetadata) -> Result<(), ()> {
-        match self.socket.as_ref() {
-            Some(socket) => {
-                let endpoint = UdpMetadata::from(endpoint);
-                info!("Sending some data to {:?}", endpoint);
-                block_on(socket.send_to(&data, endpoint)).map_err(|_| ())
-            }
-            None => {
-                info!("Unintialized Socket");
-                Err(())
+    async fn send(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        data: Vec<u8>,
+        endpoint: gen_udp::UdpMetadata,
+    ) -> Result<(), UdpError> {
+        let endpoint = UdpMetadata::try_from(endpoint)?;
+        self.policy.check_remote(&endpoint.endpoint)?;
+        self.policy.consume(data.len())?;
+        info!("Sending some data to {:?}", endpoint);
+        self.socket(&self_)
+            .socket
+            .run(|socket| socket.send_to(&data, endpoint))
+            .await
+            .map_err(UdpError::from)
+    }
+
+    async fn recv(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+    ) -> Result<(Vec<u8>, gen_udp::UdpMetadata), UdpError> {
+        let pooled = self.socket(&self_);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, pooled.capacity).collect();
+        loop {
+            let (n, endpoint) = pooled
+                .socket
+                .run(|socket| socket.recv_from(&mut buf))
+                .await?;
+            info!("Received some data from {:?}", endpoint);
+            // Packets from peers the capsule can't address are dropped
+            if let Ok(endpoint) = endpoint.try_into() {
+                buf.truncate(n);
+                return Ok((buf, endpoint));
             }
         }
     }
 
-    fn try_recv(&mut self) -> Result<Option<(Vec<u8>, gen_udp::UdpMetadata)>, ()> {
-        match self.socket.as_ref() {
-            Some(socket) => {
-                if !socket.may_recv() {
-                    return Ok(None);
-                }
-                let mut buf: Vec<u8> = core::iter::repeat_n(0, self.buffer_size).collect();
-                match block_on(socket.recv_from(&mut buf)) {
-                    Err(_) => Err(()),
-                    Ok((0, _)) => Ok(None),
-                    Ok((n, endpoint)) => {
-                        info!("Received some data from {:?}", endpoint);
+    fn try_recv(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+    ) -> Result<Option<(Vec<u8>, gen_udp::UdpMetadata)>, UdpError> {
+        let pooled = self.socket(&self_);
+        let mut buf: Vec<u8> = core::iter::repeat_n(0, pooled.capacity).collect();
+        // Polling without registering interest, the capsule will ask again
+        let mut cx = Context::from_waker(Waker::noop());
+        loop {
+            match pooled.socket.poll_recv_from(&mut buf, &mut cx) {
+                Poll::Pending => return Ok(None),
+                Poll::Ready(result) => {
+                    let (n, endpoint) = result?;
+                    info!("Received some data from {:?}", endpoint);
+                    // Packets from peers the capsule can't address are dropped
+                    if let Ok(endpoint) = endpoint.try_into() {
                         buf.truncate(n);
-                        Ok(Some((buf, endpoint.into())))
+                        return Ok(Some((buf, endpoint)));
                     }
                 }
             }
-            None => {
-                info!("Unintialized Socket");
-                Err(())
+        }
+    }
+
+    fn join_multicast(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        group: IpAddr,
+    ) -> Result<(), UdpError> {
+        #[cfg(feature = "multicast")]
+        {
+            let group = IpAddress::try_from(group)?;
+            if !group.is_multicast() {
+                return Err(UdpError::InvalidArgument);
+            }
+            if !self.allowed_groups.contains(&group) {
+                return Err(UdpError::PermissionDenied);
+            }
+            let stack = self.stack.ok_or(UdpError::PermissionDenied)?;
+            let groups = &mut self.socket(&self_).groups;
+            if !groups.contains(&group) {
+                acquire_group(*stack, group)?;
+                groups.push(group);
+            }
+            Ok(())
+        }
+        #[cfg(not(feature = "multicast"))]
+        {
+            let _ = (self_, group);
+            Err(UdpError::PermissionDenied)
+        }
+    }
+
+    fn leave_multicast(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        group: IpAddr,
+    ) -> Result<(), UdpError> {
+        #[cfg(feature = "multicast")]
+        {
+            let group = IpAddress::try_from(group)?;
+            let stack = self.stack;
+            let groups = &mut self.socket(&self_).groups;
+            let index = groups
+                .iter()
+                .position(|g| *g == group)
+                .ok_or(UdpError::InvalidArgument)?;
+            groups.swap_remove(index);
+            if let Some(stack) = stack {
+                release_group(*stack, group);
             }
+            Ok(())
+        }
+        #[cfg(not(feature = "multicast"))]
+        {
+            let _ = (self_, group);
+            Err(UdpError::PermissionDenied)
+        }
+    }
+
+    fn set_hop_limit(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        hop_limit: Option<u8>,
+    ) -> Result<(), UdpError> {
+        if hop_limit == Some(0) {
+            return Err(UdpError::InvalidArgument);
         }
+        self.socket(&self_).socket.set_hop_limit(hop_limit);
+        Ok(())
     }
 
-    fn drop(&mut self, _: Resource<gen_udp::UdpSocket>) -> wasmtime::Result<()> {
-        unreachable!()
+    fn drop(&mut self, rep: Resource<gen_udp::UdpSocket>) -> wasmtime::Result<()> {
+        if let Some(mut pooled) = self
+            .sockets
+            .get_mut(rep.rep() as usize)
+            .and_then(Option::take)
+        {
+            // Releases the port so the socket can be bound again
+            pooled.socket.close();
+            #[cfg(feature = "multicast")]
+            if let Some(stack) = self.stack {
+                for group in core::mem::take(&mut pooled.groups) {
+                    release_group(*stack, group);
+                }
+            }
+            self.free.push(pooled);
+        }
+        Ok(())
     }
 }
 
@@ -210,50 +452,163 @@ impl Host for ArielUDPHost {}
 impl Host for ArielOSHost {}
 
 impl HostUdpSocket for ArielOSHost {
-    fn bind(&mut self, port: u16) -> Result<(), ()> {
+    fn bind(&mut self, port: u16) -> Result<Resource<gen_udp::UdpSocket>, UdpError> {
         self.udp_host.bind(port)
     }
 
-    fn send(
+    async fn send(
         &mut self,
-        data: wasmtime::component::__internal::Vec<u8>,
+        self_: Resource<gen_udp::UdpSocket>,
+        data: Vec<u8>,
         endpoint: gen_udp::UdpMetadata,
-    ) -> Result<(), ()> {
-        self.udp_host.send(data, endpoint)
+    ) -> Result<(), UdpError> {
+        self.before_deadline(
+            |host| host.udp_host.send(self_, data, endpoint),
+            Err(UdpError::TimedOut),
+        )
+        .await
+    }
+
+    async fn recv(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+    ) -> Result<(Vec<u8>, gen_udp::UdpMetadata), UdpError> {
+        self.before_deadline(|host| host.udp_host.recv(self_), Err(UdpError::TimedOut))
+            .await
     }
 
     fn try_recv(
         &mut self,
-    ) -> Result<
-        Option<(
-            wasmtime::component::__internal::Vec<u8>,
-            gen_udp::UdpMetadata,
-        )>,
-        (),
-    > {
-        self.udp_host.try_recv()
+        self_: Resource<gen_udp::UdpSocket>,
+    ) -> Result<Option<(Vec<u8>, gen_udp::UdpMetadata)>, UdpError> {
+        self.udp_host.try_recv(self_)
+    }
+
+    fn join_multicast(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        group: IpAddr,
+    ) -> Result<(), UdpError> {
+        self.udp_host.join_multicast(self_, group)
+    }
+
+    fn leave_multicast(
+        &mut self,
+        self_: Resource<gen_udp::UdpSocket>,
+        group: IpAddr,
+    ) -> Result<(), UdpError> {
+        self.udp_host.leave_multicast(self_, group)
     }
 
-    fn drop(
+    fn set_hop_limit(
         &mut self,
-        rep: wasmtime::component::Resource<gen_udp::UdpSocket>,
-    ) -> wasmtime::Result<()> {
+        self_: Resource<gen_udp::UdpSocket>,
+        hop_limit: Option<u8>,
+    ) -> Result<(), UdpError> {
+        self.udp_host.set_hop_limit(self_, hop_limit)
+    }
+
+    fn drop(&mut self, rep: Resource<gen_udp::UdpSocket>) -> wasmtime::Result<()> {
         self.udp_host.drop(rep)
     }
 }
 
 impl ArielOSHost {
-    pub unsafe fn initialize_socket(
+    /// Lets the capsule open one more socket, which uses these buffers.
+    ///
+    /// The capsule can have as many sockets bound at once as buffers were added.
+    ///
+    /// # Safety
+    ///
+    /// The host must only be used, and dropped, on the executor running `stack`: the sockets
+    /// of embassy-net can't be accessed from other threads or cores.
+    pub unsafe fn add_udp_buffers(
         &mut self,
         stack: ariel_os_embassy::NetworkStack,
-        rx_meta: &mut [ariel_os_embassy::reexports::embassy_net::udp::PacketMetadata],
-        rx_buffer: &mut [u8],
-        tx_meta: &mut [ariel_os_embassy::reexports::embassy_net::udp::PacketMetadata],
-        tx_buffer: &mut [u8],
+        rx_meta: &'static mut [PacketMetadata],
+        rx_buffer: &'static mut [u8],
+        tx_meta: &'static mut [PacketMetadata],
+        tx_buffer: &'static mut [u8],
     ) {
+        // SAFETY: Required from the caller
         unsafe {
             self.udp_host
-                .initialize_socket(stack, rx_meta, rx_buffer, tx_meta, tx_buffer)
+                .add_buffers(stack, rx_meta, rx_buffer, tx_meta, tx_buffer);
+        }
+    }
+
+    /// Lets the capsule open one more socket, which uses `buffers`.
+    ///
+    /// # Safety
+    ///
+    /// As for [`ArielOSHost::add_udp_buffers`].
+    pub unsafe fn add_udp_socket<const PACKETS: usize, const SIZE: usize>(
+        &mut self,
+        stack: ariel_os_embassy::NetworkStack,
+        buffers: &'static mut UdpSocketBuffers<PACKETS, SIZE>,
+    ) {
+        let UdpSocketBuffers {
+            rx_meta,
+            rx_buffer,
+            tx_meta,
+            tx_buffer,
+        } = buffers;
+        // SAFETY: Required from the caller
+        unsafe { self.add_udp_buffers(stack, rx_meta, rx_buffer, tx_meta, tx_buffer) };
+    }
+
+    /// Lets the capsule join the multicast `group`.
+    #[cfg(feature = "multicast")]
+    pub fn allow_udp_multicast_group(&mut self, group: IpAddress) {
+        self.udp_host.allowed_groups.push(group);
+    }
+
+    /// Restricts the ports the capsule may bind, the endpoints it may send to and its rate.
+    pub fn set_udp_policy(&mut self, policy: NetPolicy) {
+        self.udp_host.policy = policy;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use crate::wasm::ip_types::{Endpoint, Ipv4Addr};
+
+    #[test]
+    fn keeps_unicast_local_address() {
+        let endpoint = Endpoint {
+            addr: IpAddr::V4(Ipv4Addr {
+                a: 192,
+                b: 0,
+                c: 2,
+                d: 1,
+            }),
+            port: 5683,
+        };
+        let local = |a| {
+            Some(IpAddr::V4(Ipv4Addr {
+                a,
+                b: 0,
+                c: 2,
+                d: 2,
+            }))
         };
+
+        let unicast = UdpMetadata::try_from(gen_udp::UdpMetadata {
+            endpoint,
+            local_addr: local(192),
+        })
+        .unwrap();
+        assert_eq!(unicast.endpoint, (IpAddress::v4(192, 0, 2, 1), 5683).into());
+        assert_eq!(unicast.local_address, Some(IpAddress::v4(192, 0, 2, 2)));
+
+        // A packet received on a multicast group is answered from the default address
+        let multicast = UdpMetadata::try_from(gen_udp::UdpMetadata {
+            endpoint,
+            local_addr: local(224),
+        })
+        .unwrap();
+        assert_eq!(multicast.local_address, None);
     }
 }
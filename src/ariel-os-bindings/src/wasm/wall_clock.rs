@@ -0,0 +1,404 @@
+use core::cell::Cell;
+
+use ariel_os_embassy::api::time::Instant;
+
+use embassy_sync::blocking_mutex::{Mutex, raw::CriticalSectionRawMutex};
+
+use super::ArielOSHost;
+
+mod bindings {
+    // In its own module, as the bindings of the world are named like [`super::WallClock`]
+    wasmtime::component::bindgen!({
+        world: "ariel:wasm-bindings/wall-clock",
+        path: "../../wit/",
+    });
+}
+
+pub use bindings::ariel::wasm_bindings::wall_clock_api::{
+    Datetime, Host, HostWithStore, SyncSource, SyncStatus, add_to_linker,
+};
+
+#[derive(Clone, Copy)]
+struct ClockState {
+    /// UTC time at boot, in microseconds since the Unix epoch.
+    boot_unix_micros: u64,
+    source: SyncSource,
+    synced_at: Instant,
+}
+
+/// UTC clock shared between the capsules and whatever synchronizes it.
+///
+/// The clock is kept as the UTC time of the boot instant, so reading it is only a matter of adding
+/// the monotonic time.
+pub struct WallClock {
+    state: Mutex<CriticalSectionRawMutex, Cell<Option<ClockState>>>,
+}
+
+impl WallClock {
+    pub const fn new() -> Self {
+        Self {
+            state: Mutex::new(Cell::new(None)),
+        }
+    }
+
+    /// Sets the clock given that it is `unix_micros` at the `at` instant.
+    pub fn set_at(&self, unix_micros: u64, at: Instant, source: SyncSource) {
+        let state = ClockState {
+            boot_unix_micros: unix_micros.saturating_sub(at.as_micros()),
+            source,
+            synced_at: Instant::now(),
+        };
+        self.state.lock(|s| s.set(Some(state)));
+    }
+
+    /// Sets the clock manually to `unix_micros`.
+    pub fn set_manually(&self, unix_micros: u64) {
+        self.set_at(unix_micros, Instant::now(), SyncSource::Manual);
+    }
+
+    /// Current UTC time in microseconds since the Unix epoch, if the clock was ever set.
+    pub fn now_unix_micros(&self) -> Option<u64> {
+        self.state.lock(|s| s.get()).map(|s| {
+            s.boot_unix_micros
+                .saturating_add(Instant::now().as_micros())
+        })
+    }
+
+    pub fn status(&self) -> SyncStatus {
+        match self.state.lock(|s| s.get()) {
+            Some(state) => SyncStatus {
+                source: state.source,
+                last_sync_micros: state.synced_at.as_micros(),
+            },
+            None => SyncStatus {
+                source: SyncSource::None,
+                last_sync_micros: 0,
+            },
+        }
+    }
+}
+
+impl Default for WallClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct ArielWallClockHost {
+    pub(crate) clock: Option<&'static WallClock>,
+}
+
+impl Host for ArielWallClockHost {
+    fn now(&mut self) -> Option<Datetime> {
+        let micros = self.clock?.now_unix_micros()?;
+        Some(Datetime {
+            seconds: micros / 1_000_000,
+            nanoseconds: (micros % 1_000_000) as u32 * 1000,
+        })
+    }
+
+    fn status(&mut self) -> SyncStatus {
+        match self.clock {
+            Some(clock) => clock.status(),
+            None => SyncStatus {
+                source: SyncSource::None,
+                last_sync_micros: 0,
+            },
+        }
+    }
+}
+
+impl Host for ArielOSHost {
+    fn now(&mut self) -> Option<Datetime> {
+        self.wall_clock_host.now()
+    }
+
+    fn status(&mut self) -> SyncStatus {
+        self.wall_clock_host.status()
+    }
+}
+
+impl ArielOSHost {
+    /// Gives the capsule read access to `clock`.
+    pub fn bind_wall_clock(&mut self, clock: &'static WallClock) {
+        self.wall_clock_host.clock = Some(clock);
+    }
+}
+
+#[cfg(feature = "sntp")]
+pub mod sntp {
+    //! Minimal SNTPv4 client (RFC 4330) keeping a [`WallClock`] synchronized.
+
+    use super::{SyncSource, WallClock};
+
+    use ariel_os_embassy::api::time::{Duration, Instant, Timer};
+    use ariel_os_embassy::reexports::embassy_net;
+
+    use embassy_futures::select::{Either, select};
+    use embassy_net::IpEndpoint;
+    use embassy_net::udp::{PacketMetadata, UdpSocket};
+
+    use rand_core::RngCore as _;
+
+    /// Standard NTP port.
+    pub const NTP_PORT: u16 = 123;
+
+    const PACKET_LEN: usize = 48;
+
+    /// Seconds between the NTP epoch (1900) and the Unix epoch (1970).
+    const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+    /// LI = 0, VN = 4, Mode = 3 (client).
+    const CLIENT_HEADER: u8 = 0b00_100_011;
+
+    #[derive(Debug, Clone, Copy, PartialEq, Eq)]
+    pub enum SntpError {
+        /// The socket could not be bound or the request not sent.
+        Network,
+        /// No response arrived in time.
+        Timeout,
+        /// The response is malformed, doesn't match the request or is a kiss-o'-death.
+        InvalidResponse,
+    }
+
+    /// Converts a 32.32 fixed point NTP timestamp to microseconds since the Unix epoch.
+    fn ntp_to_unix_micros(timestamp: u64) -> Option<u64> {
+        let seconds = (timestamp >> 32).checked_sub(NTP_UNIX_OFFSET)?;
+        let fraction = ((timestamp & 0xffff_ffff) * 1_000_000) >> 32;
+        Some(seconds * 1_000_000 + fraction)
+    }
+
+    fn read_timestamp(packet: &[u8; PACKET_LEN], offset: usize) -> u64 {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(&packet[offset..offset + 8]);
+        u64::from_be_bytes(bytes)
+    }
+
+    /// Builds a client request, `cookie` is sent as transmit timestamp and echoed by the server.
+    pub fn build_request(cookie: u64) -> [u8; PACKET_LEN] {
+        let mut packet = [0; PACKET_LEN];
+        packet[0] = CLIENT_HEADER;
+        packet[40..48].copy_from_slice(&cookie.to_be_bytes());
+        packet
+    }
+
+    /// Computes the UTC time, in microseconds since the Unix epoch, at which `response` was
+    /// received.
+    ///
+    /// `rtt_micros` is the time elapsed between sending the request and receiving the response,
+    /// half of it minus the server processing time is accounted for as transmission delay.
+    pub fn parse_response(
+        response: &[u8; PACKET_LEN],
+        cookie: u64,
+        rtt_micros: u64,
+    ) -> Result<u64, SntpError> {
+        let mode = response[0] & 0x7;
+        let version = (response[0] >> 3) & 0x7;
+        let stratum = response[1];
+        // Mode 4 is a server response, stratum 0 is a kiss-o'-death
+        if mode != 4 || !(3..=4).contains(&version) || stratum == 0 {
+            return Err(SntpError::InvalidResponse);
+        }
+        if read_timestamp(response, 24) != cookie {
+            return Err(SntpError::InvalidResponse);
+        }
+
+        let received =
+            ntp_to_unix_micros(read_timestamp(response, 32)).ok_or(SntpError::InvalidResponse)?;
+        let transmitted =
+            ntp_to_unix_micros(read_timestamp(response, 40)).ok_or(SntpError::InvalidResponse)?;
+        let processing = transmitted.saturating_sub(received);
+        let delay = rtt_micros.saturating_sub(processing) / 2;
+
+        Ok(transmitted + delay)
+    }
+
+    /// Queries `server` once and sets `clock` on success.
+    pub async fn sync_once(
+        stack: ariel_os_embassy::NetworkStack,
+        server: IpEndpoint,
+        clock: &WallClock,
+        timeout: Duration,
+    ) -> Result<(), SntpError> {
+        let mut rx_meta = [PacketMetadata::EMPTY; 1];
+        let mut rx_buffer = [0; PACKET_LEN];
+        let mut tx_meta = [PacketMetadata::EMPTY; 1];
+        let mut tx_buffer = [0; PACKET_LEN];
+        let mut socket = UdpSocket::new(
+            stack,
+            &mut rx_meta,
+            &mut rx_buffer,
+            &mut tx_meta,
+            &mut tx_buffer,
+        );
+        // Port 0 lets the stack pick an ephemeral port
+        socket.bind(0).map_err(|_| SntpError::Network)?;
+
+        // Unpredictable, so that only the server can answer with it
+        let cookie = ariel_os_random::crypto_rng().next_u64();
+        let sent_at = Instant::now();
+        socket
+            .send_to(&build_request(cookie), server)
+            .await
+            .map_err(|_| SntpError::Network)?;
+
+        let deadline = Timer::at(sent_at + timeout);
+        let receive = async {
+            let mut response = [0; PACKET_LEN];
+            loop {
+                match socket.recv_from(&mut response).await {
+                    Ok((PACKET_LEN, meta)) if meta.endpoint == server => {
+                        let received_at = Instant::now();
+                        let rtt = (received_at - sent_at).as_micros();
+                        // Responses that don't match are ignored, the real one may still come
+                        if let Ok(unix_micros) = parse_response(&response, cookie, rtt) {
+                            return (unix_micros, received_at);
+                        }
+                    }
+                    _ => continue,
+                }
+            }
+        };
+
+        match select(receive, deadline).await {
+            Either::First((unix_micros, received_at)) => {
+                clock.set_at(unix_micros, received_at, SyncSource::Sntp);
+                Ok(())
+            }
+            Either::Second(()) => Err(SntpError::Timeout),
+        }
+    }
+
+    /// Keeps `clock` synchronized with `server`, querying it every `interval`.
+    ///
+    /// Failed queries are retried after a tenth of the interval.
+    pub async fn run(
+        stack: ariel_os_embassy::NetworkStack,
+        server: IpEndpoint,
+        clock: &WallClock,
+        interval: Duration,
+    ) -> ! {
+        loop {
+            let wait = match sync_once(stack, server, clock, Duration::from_secs(5)).await {
+                Ok(()) => interval,
+                Err(_) => interval / 10,
+            };
+            Timer::after(wait).await;
+        }
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+
+        /// 2023-11-14T22:13:20Z, the NTP seconds of Unix time 1_700_000_000.
+        const NTP_SECONDS: u64 = 1_700_000_000 + NTP_UNIX_OFFSET;
+        const UNIX_MICROS: u64 = 1_700_000_000_000_000;
+        const COOKIE: u64 = 0x0123_4567_89ab_cdef;
+
+        /// Server response to `COOKIE` received at `NTP_SECONDS` and sent 0.25 s later.
+        fn response() -> [u8; PACKET_LEN] {
+            let mut packet = [0; PACKET_LEN];
+            // LI = 0, VN = 4, Mode = 4 (server)
+            packet[0] = 0b00_100_100;
+            packet[1] = 2;
+            packet[24..32].copy_from_slice(&COOKIE.to_be_bytes());
+            packet[32..40].copy_from_slice(&(NTP_SECONDS << 32).to_be_bytes());
+            packet[40..48].copy_from_slice(&((NTP_SECONDS << 32) | 0x4000_0000).to_be_bytes());
+            packet
+        }
+
+        #[test]
+        fn converts_timestamps() {
+            assert_eq!(ntp_to_unix_micros(NTP_UNIX_OFFSET << 32), Some(0));
+            assert_eq!(ntp_to_unix_micros(NTP_SECONDS << 32), Some(UNIX_MICROS));
+            assert_eq!(
+                ntp_to_unix_micros((NTP_SECONDS << 32) | 0x8000_0000),
+                Some(UNIX_MICROS + 500_000)
+            );
+            assert_eq!(
+                ntp_to_unix_micros(u64::MAX),
+                Some((u64::from(u32::MAX) - NTP_UNIX_OFFSET) * 1_000_000 + 999_999)
+            );
+            // Before 1970
+            assert_eq!(ntp_to_unix_micros((NTP_UNIX_OFFSET - 1) << 32), None);
+        }
+
+        #[test]
+        fn builds_request() {
+            let request = build_request(COOKIE);
+            assert_eq!(request[0], 0x23);
+            assert_eq!(request[40..48], COOKIE.to_be_bytes());
+            assert!(request[1..40].iter().all(|&b| b == 0));
+        }
+
+        #[test]
+        fn parses_response() {
+            // 0.25 s of processing leave 0.1 s of transmission each way
+            assert_eq!(
+                parse_response(&response(), COOKIE, 450_000),
+                Ok(UNIX_MICROS + 250_000 + 100_000)
+            );
+            // A round trip shorter than the processing time doesn't go back in time
+            assert_eq!(
+                parse_response(&response(), COOKIE, 0),
+                Ok(UNIX_MICROS + 250_000)
+            );
+
+            // SNTPv3 servers are accepted too
+            let mut v3 = response();
+            v3[0] = 0b00_011_100;
+            assert_eq!(parse_response(&v3, COOKIE, 0), Ok(UNIX_MICROS + 250_000));
+        }
+
+        #[test]
+        fn rejects_wrong_mode_and_version() {
+            for header in [
+                // Mode 3, the client's own request
+                0b00_100_011,
+                // Mode 5, broadcast
+                0b00_100_101,
+                // Version 2 and 5
+                0b00_010_100,
+                0b00_101_100,
+            ] {
+                let mut packet = response();
+                packet[0] = header;
+                assert_eq!(
+                    parse_response(&packet, COOKIE, 0),
+                    Err(SntpError::InvalidResponse)
+                );
+            }
+        }
+
+        #[test]
+        fn rejects_cookie_mismatch() {
+            assert_eq!(
+                parse_response(&response(), COOKIE ^ 1, 0),
+                Err(SntpError::InvalidResponse)
+            );
+        }
+
+        #[test]
+        fn rejects_kiss_of_death() {
+            let mut packet = response();
+            packet[1] = 0;
+            packet[12..16].copy_from_slice(b"RATE");
+            assert_eq!(
+                parse_response(&packet, COOKIE, 0),
+                Err(SntpError::InvalidResponse)
+            );
+        }
+
+        #[test]
+        fn rejects_timestamps_before_1970() {
+            let mut packet = response();
+            packet[40..48].copy_from_slice(&(1u64 << 32).to_be_bytes());
+            assert_eq!(
+                parse_response(&packet, COOKIE, 0),
+                Err(SntpError::InvalidResponse)
+            );
+        }
+    }
+}
@@ -0,0 +1,245 @@
+//! Moving the format strings out of compiled modules.
+
+extern crate alloc;
+use alloc::string::String;
+use alloc::vec::Vec;
+
+use core::fmt;
+
+use crate::{SECTION, entries};
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ExtractError {
+    /// The input doesn't start with the header of a wasm module.
+    NotWasm,
+    /// A section, or the name of a custom section, goes past the end of the module.
+    Truncated,
+    /// The format strings of the module are not a valid table.
+    MalformedTable,
+    /// Two different format strings have the same identifier.
+    ///
+    /// The host couldn't tell their entries apart, one of them has to be reworded.
+    Collision {
+        id: u32,
+        first: String,
+        second: String,
+    },
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::NotWasm => write!(f, "not a wasm module"),
+            Self::Truncated => write!(f, "truncated section"),
+            Self::MalformedTable => write!(f, "malformed `{SECTION}` section"),
+            Self::Collision { id, first, second } => write!(
+                f,
+                "format strings {first:?} and {second:?} have the same identifier {id:#010x}"
+            ),
+        }
+    }
+}
+
+impl core::error::Error for ExtractError {}
+
+fn read_leb128(data: &[u8], pos: &mut usize) -> Result<usize, ExtractError> {
+    let mut result = 0;
+    let mut shift = 0;
+    loop {
+        let byte = *data.get(*pos).ok_or(ExtractError::Truncated)?;
+        *pos += 1;
+        result |= usize::from(byte & 0x7f) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(result);
+        }
+        shift += 7;
+        if shift > 28 {
+            return Err(ExtractError::Truncated);
+        }
+    }
+}
+
+/// Splits a wasm module into the module without its `ariel-log` custom sections and the table
+/// of the format strings they hold.
+///
+/// A format string used in several places is only kept once in the table.
+pub fn extract_log_formats(wasm: &[u8]) -> Result<(Vec<u8>, Vec<u8>), ExtractError> {
+    if wasm.get(..4) != Some(b"\0asm") || wasm.len() < 8 {
+        return Err(ExtractError::NotWasm);
+    }
+    // Magic number and version
+    let mut pos = 8;
+    let mut stripped = wasm[..pos].to_vec();
+    let mut sections = Vec::new();
+
+    while pos < wasm.len() {
+        let start = pos;
+        let id = wasm[pos];
+        pos += 1;
+        let size = read_leb128(wasm, &mut pos)?;
+        let end = pos.checked_add(size).ok_or(ExtractError::Truncated)?;
+        if end > wasm.len() {
+            return Err(ExtractError::Truncated);
+        }
+
+        if id == 0 {
+            let name_len = read_leb128(wasm, &mut pos)?;
+            let name_end = pos.checked_add(name_len).ok_or(ExtractError::Truncated)?;
+            if name_end > end {
+                return Err(ExtractError::Truncated);
+            }
+            if &wasm[pos..name_end] == SECTION.as_bytes() {
+                sections.extend_from_slice(&wasm[name_end..end]);
+                pos = end;
+                continue;
+            }
+        }
+
+        stripped.extend_from_slice(&wasm[start..end]);
+        pos = end;
+    }
+
+    let mut seen: Vec<(u32, &str)> = Vec::new();
+    let mut formats = Vec::new();
+    let mut table = entries(&sections);
+    for (id, format) in table.by_ref() {
+        match seen.iter().find(|(seen_id, _)| *seen_id == id) {
+            Some((_, first)) if *first == format => {}
+            Some((_, first)) => {
+                return Err(ExtractError::Collision {
+                    id,
+                    first: String::from(*first),
+                    second: String::from(format),
+                });
+            }
+            None => {
+                seen.push((id, format));
+                formats.extend_from_slice(&id.to_le_bytes());
+                formats.extend_from_slice(&(format.len() as u16).to_le_bytes());
+                formats.extend_from_slice(format.as_bytes());
+            }
+        }
+    }
+    if !table.is_exhausted() {
+        return Err(ExtractError::MalformedTable);
+    }
+
+    Ok((stripped, formats))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{entry, entry_len, format_id};
+
+    const HEADER: &[u8] = b"\0asm\x01\0\0\0";
+
+    fn custom_section(name: &str, content: &[u8]) -> Vec<u8> {
+        let size = 1 + name.len() + content.len();
+        assert!(size < 0x80 && name.len() < 0x80);
+        let mut section = vec![0, size as u8, name.len() as u8];
+        section.extend_from_slice(name.as_bytes());
+        section.extend_from_slice(content);
+        section
+    }
+
+    fn module(sections: &[&[u8]]) -> Vec<u8> {
+        let mut wasm = HEADER.to_vec();
+        for section in sections {
+            wasm.extend_from_slice(section);
+        }
+        wasm
+    }
+
+    const FORMAT: &str = "value {}";
+    const OTHER: &str = "other {} {}";
+
+    #[test]
+    fn moves_formats_out() {
+        let types = [1, 1, 0];
+        let name = custom_section("name", b"x");
+        let log = custom_section(SECTION, &entry::<{ entry_len(FORMAT) }>(FORMAT));
+        let wasm = module(&[&types, &log, &name]);
+
+        let (stripped, formats) = extract_log_formats(&wasm).unwrap();
+        assert_eq!(stripped, module(&[&types, &name]));
+        assert_eq!(
+            entries(&formats).collect::<Vec<_>>(),
+            [(format_id(FORMAT), FORMAT)]
+        );
+    }
+
+    #[test]
+    fn merges_sections_and_repeated_formats() {
+        let mut content = entry::<{ entry_len(FORMAT) }>(FORMAT).to_vec();
+        content.extend_from_slice(&entry::<{ entry_len(OTHER) }>(OTHER));
+        content.extend_from_slice(&entry::<{ entry_len(FORMAT) }>(FORMAT));
+        let first = custom_section(SECTION, &content);
+        let second = custom_section(SECTION, &entry::<{ entry_len(OTHER) }>(OTHER));
+
+        let (stripped, formats) = extract_log_formats(&module(&[&first, &second])).unwrap();
+        assert_eq!(stripped, HEADER);
+        assert_eq!(
+            entries(&formats).collect::<Vec<_>>(),
+            [(format_id(FORMAT), FORMAT), (format_id(OTHER), OTHER)]
+        );
+    }
+
+    #[test]
+    fn no_formats() {
+        let wasm = module(&[&[1, 1, 0]]);
+        assert_eq!(extract_log_formats(&wasm).unwrap(), (wasm, Vec::new()));
+    }
+
+    #[test]
+    fn rejects_colliding_ids() {
+        let mut content = entry::<{ entry_len(FORMAT) }>(FORMAT).to_vec();
+        // Same identifier as `FORMAT`, different string
+        let mut colliding = entry::<{ entry_len(OTHER) }>(OTHER);
+        colliding[..4].copy_from_slice(&format_id(FORMAT).to_le_bytes());
+        content.extend_from_slice(&colliding);
+        let wasm = module(&[&custom_section(SECTION, &content)]);
+
+        assert_eq!(
+            extract_log_formats(&wasm),
+            Err(ExtractError::Collision {
+                id: format_id(FORMAT),
+                first: String::from(FORMAT),
+                second: String::from(OTHER),
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_input() {
+        assert_eq!(extract_log_formats(b"\0asm"), Err(ExtractError::NotWasm));
+        assert_eq!(
+            extract_log_formats(b"\x7fELF\x01\0\0\0"),
+            Err(ExtractError::NotWasm)
+        );
+
+        // Section longer than the module
+        assert_eq!(
+            extract_log_formats(&module(&[&[1, 5, 0]])),
+            Err(ExtractError::Truncated)
+        );
+        // Unterminated LEB128 size
+        assert_eq!(
+            extract_log_formats(&module(&[&[1, 0x80]])),
+            Err(ExtractError::Truncated)
+        );
+        // Name of the custom section longer than the section
+        assert_eq!(
+            extract_log_formats(&module(&[&[0, 2, 9, b'a']])),
+            Err(ExtractError::Truncated)
+        );
+
+        // Entry cut in the middle of its format string
+        let entry = entry::<{ entry_len(FORMAT) }>(FORMAT);
+        let log = custom_section(SECTION, &entry[..entry.len() - 1]);
+        assert_eq!(
+            extract_log_formats(&module(&[&log])),
+            Err(ExtractError::MalformedTable)
+        );
+    }
+}
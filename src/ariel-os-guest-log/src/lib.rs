@@ -0,0 +1,328 @@
+//! Deferred-format logging for capsules.
+//!
+//! Instead of formatting log entries inside the capsule, which pulls `core::fmt` into the payload,
+//! the [`log!`] macro sends the identifier of the format string and the raw arguments to the
+//! `log-deferred` function of `log-api`, and the host does the formatting.
+//!
+//! The format strings are placed in the `ariel-log` custom section of the module, which
+//! `precompile_wasm.rs` moves out of the payload into a table the host is given.
+//!
+//! The macros expect the bindings of `log-api` to have been generated at the root of the crate
+//! (as `wit_bindgen::generate!` does by default).
+//!
+//! # Encoding
+//!
+//! A table entry is the identifier (`u32`), the length of the format string (`u16`) and the
+//! format string itself. Arguments are a tag byte followed by the value, see [`tag`]. Every
+//! integer is little-endian.
+//!
+//! # Extraction
+//!
+//! With the `extract` feature, [`extract::extract_log_formats`] splits a compiled module into the
+//! module without the format strings and their table. This is what `precompile_wasm.rs` uses.
+#![cfg_attr(not(test), no_std)]
+#![allow(
+    clippy::crate_in_macro_def,
+    reason = "The bindings are generated in the crate using the macros"
+)]
+
+/// Name of the custom section holding the format strings.
+pub const SECTION: &str = "ariel-log";
+
+/// Maximum size of the encoded arguments of a single entry.
+pub const BUFFER_SIZE: usize = 64;
+
+/// Tags preceding each encoded argument.
+pub mod tag {
+    pub const U32: u8 = 0;
+    pub const I32: u8 = 1;
+    pub const U64: u8 = 2;
+    pub const I64: u8 = 3;
+    pub const F32: u8 = 4;
+    pub const F64: u8 = 5;
+    pub const BOOL: u8 = 6;
+    /// Followed by a `u16` length and the UTF-8 bytes.
+    pub const STR: u8 = 7;
+}
+
+/// Identifier of a format string (32-bit FNV-1a hash).
+pub const fn format_id(format: &str) -> u32 {
+    let bytes = format.as_bytes();
+    let mut hash: u32 = 0x811c_9dc5;
+    let mut i = 0;
+    while i < bytes.len() {
+        hash ^= bytes[i] as u32;
+        hash = hash.wrapping_mul(0x0100_0193);
+        i += 1;
+    }
+    hash
+}
+
+/// Size of the table entry of `format`.
+pub const fn entry_len(format: &str) -> usize {
+    4 + 2 + format.len()
+}
+
+/// Iterates over the `(identifier, format string)` entries of a table.
+///
+/// Iteration stops at the first malformed entry.
+pub fn entries(table: &[u8]) -> Entries<'_> {
+    Entries { rest: table }
+}
+
+/// Iterator returned by [`entries`].
+pub struct Entries<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Entries<'a> {
+    /// Whether the whole table was read without encountering a malformed entry.
+    pub fn is_exhausted(&self) -> bool {
+        self.rest.is_empty()
+    }
+}
+
+impl<'a> Iterator for Entries<'a> {
+    type Item = (u32, &'a str);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let header = self.rest.get(..6)?;
+        let id = u32::from_le_bytes([header[0], header[1], header[2], header[3]]);
+        let len = usize::from(u16::from_le_bytes([header[4], header[5]]));
+        let format = core::str::from_utf8(self.rest.get(6..6 + len)?).ok()?;
+        self.rest = &self.rest[6 + len..];
+        Some((id, format))
+    }
+}
+
+/// Table entry of `format`, `N` has to be [`entry_len`].
+pub const fn entry<const N: usize>(format: &str) -> [u8; N] {
+    let bytes = format.as_bytes();
+    assert!(N == entry_len(format));
+    assert!(bytes.len() <= u16::MAX as usize, "Format string too long");
+
+    let mut entry = [0; N];
+    let id = format_id(format).to_le_bytes();
+    let len = (bytes.len() as u16).to_le_bytes();
+    entry[0] = id[0];
+    entry[1] = id[1];
+    entry[2] = id[2];
+    entry[3] = id[3];
+    entry[4] = len[0];
+    entry[5] = len[1];
+    let mut i = 0;
+    while i < bytes.len() {
+        entry[6 + i] = bytes[i];
+        i += 1;
+    }
+    entry
+}
+
+/// Buffer the arguments of an entry are encoded into.
+///
+/// Arguments that don't fit anymore are dropped, the host renders them as missing.
+pub struct Encoder {
+    buf: [u8; BUFFER_SIZE],
+    /// End of the last complete argument.
+    len: usize,
+    full: bool,
+}
+
+impl Encoder {
+    pub const fn new() -> Self {
+        Self {
+            buf: [0; BUFFER_SIZE],
+            len: 0,
+            full: false,
+        }
+    }
+
+    /// Appends an argument made of `tag` followed by the `parts`.
+    pub fn push(&mut self, tag: u8, parts: &[&[u8]]) {
+        let size = 1 + parts.iter().map(|p| p.len()).sum::<usize>();
+        // Once full, stay full so arguments are never skipped in the middle
+        if self.full || self.len + size > BUFFER_SIZE {
+            self.full = true;
+            return;
+        }
+        self.buf[self.len] = tag;
+        self.len += 1;
+        for part in parts {
+            self.buf[self.len..self.len + part.len()].copy_from_slice(part);
+            self.len += part.len();
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.buf[..self.len]
+    }
+}
+
+impl Default for Encoder {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Values that can be passed as arguments to the logging macros.
+pub trait Arg {
+    fn encode(&self, encoder: &mut Encoder);
+}
+
+macro_rules! impl_arg {
+    ($tag:expr, $wire:ty, $($t:ty),*) => {
+        $(
+            impl Arg for $t {
+                fn encode(&self, encoder: &mut Encoder) {
+                    encoder.push($tag, &[&<$wire>::from(*self).to_le_bytes()]);
+                }
+            }
+        )*
+    };
+}
+
+impl_arg!(tag::U32, u32, u8, u16, u32);
+impl_arg!(tag::I32, i32, i8, i16, i32);
+impl_arg!(tag::U64, u64, u64);
+impl_arg!(tag::I64, i64, i64);
+impl_arg!(tag::F32, f32, f32);
+impl_arg!(tag::F64, f64, f64);
+
+impl Arg for bool {
+    fn encode(&self, encoder: &mut Encoder) {
+        encoder.push(tag::BOOL, &[&[u8::from(*self)]]);
+    }
+}
+
+impl Arg for str {
+    fn encode(&self, encoder: &mut Encoder) {
+        let len = self.len().min(u16::MAX as usize);
+        encoder.push(
+            tag::STR,
+            &[&(len as u16).to_le_bytes(), &self.as_bytes()[..len]],
+        );
+    }
+}
+
+impl<T: Arg + ?Sized> Arg for &T {
+    fn encode(&self, encoder: &mut Encoder) {
+        (**self).encode(encoder)
+    }
+}
+
+#[cfg(feature = "extract")]
+pub mod extract;
+
+/// Logs an entry at the given `log-api` level, formatted by the host.
+///
+/// Only `{}` placeholders are supported in the format string.
+#[macro_export]
+macro_rules! log {
+    ($level:expr, $format:literal $(, $arg:expr)* $(,)?) => {{
+        #[used]
+        #[unsafe(link_section = "ariel-log")]
+        static ENTRY: [u8; $crate::entry_len($format)] = $crate::entry($format);
+
+        let mut encoder = $crate::Encoder::new();
+        $( $crate::Arg::encode(&$arg, &mut encoder); )*
+        crate::ariel::wasm_bindings::log_api::log_deferred(
+            $level,
+            const { $crate::format_id($format) },
+            encoder.as_bytes(),
+        );
+    }};
+}
+
+#[macro_export]
+macro_rules! trace {
+    ($($t:tt)*) => { $crate::log!(crate::ariel::wasm_bindings::log_api::Level::Trace, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! debug {
+    ($($t:tt)*) => { $crate::log!(crate::ariel::wasm_bindings::log_api::Level::Debug, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! info {
+    ($($t:tt)*) => { $crate::log!(crate::ariel::wasm_bindings::log_api::Level::Info, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! warn {
+    ($($t:tt)*) => { $crate::log!(crate::ariel::wasm_bindings::log_api::Level::Warn, $($t)*) };
+}
+
+#[macro_export]
+macro_rules! error {
+    ($($t:tt)*) => { $crate::log!(crate::ariel::wasm_bindings::log_api::Level::Error, $($t)*) };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn encode(args: &[&dyn Arg]) -> Encoder {
+        let mut encoder = Encoder::new();
+        for arg in args {
+            arg.encode(&mut encoder);
+        }
+        encoder
+    }
+
+    #[test]
+    fn fnv1a() {
+        assert_eq!(format_id(""), 0x811c_9dc5);
+        assert_eq!(format_id("a"), 0xe40c_292c);
+        assert_eq!(format_id("foobar"), 0xbf9c_f968);
+    }
+
+    #[test]
+    fn table_entry() {
+        const FORMAT: &str = "{} ms";
+        let entry = entry::<{ entry_len(FORMAT) }>(FORMAT);
+        assert_eq!(entry[..4], format_id(FORMAT).to_le_bytes());
+        assert_eq!(entry[4..6], [5, 0]);
+        assert_eq!(&entry[6..], FORMAT.as_bytes());
+        assert_eq!(
+            entries(&entry).collect::<Vec<_>>(),
+            [(format_id(FORMAT), FORMAT)]
+        );
+    }
+
+    #[test]
+    fn malformed_table() {
+        let mut table = entries(&[1, 0, 0, 0, 3, 0, b'a']);
+        assert_eq!(table.next(), None);
+        assert!(!table.is_exhausted());
+
+        let mut table = entries(&[1, 0, 0, 0, 1, 0, 0xff]);
+        assert_eq!(table.next(), None);
+        assert!(!table.is_exhausted());
+    }
+
+    #[test]
+    fn encodes_arguments() {
+        let encoder = encode(&[&7u8, &-2i16, &u64::MAX, &1.5f32, &true, &"hi"]);
+        let mut expected = vec![tag::U32, 7, 0, 0, 0, tag::I32, 0xfe, 0xff, 0xff, 0xff];
+        expected.push(tag::U64);
+        expected.extend_from_slice(&[0xff; 8]);
+        expected.push(tag::F32);
+        expected.extend_from_slice(&1.5f32.to_le_bytes());
+        expected.extend_from_slice(&[tag::BOOL, 1, tag::STR, 2, 0, b'h', b'i']);
+        assert_eq!(encoder.as_bytes(), expected);
+    }
+
+    #[test]
+    fn stays_full() {
+        // 6 * 9 bytes fill 54 of the 64 bytes
+        let mut encoder = encode(&[&0u64, &1u64, &2u64, &3u64, &4u64, &5u64]);
+        assert_eq!(encoder.as_bytes().len(), 54);
+
+        // Doesn't fit, and nothing is added after it even if it would fit
+        "0123456789".encode(&mut encoder);
+        true.encode(&mut encoder);
+        let expected = encode(&[&0u64, &1u64, &2u64, &3u64, &4u64, &5u64]);
+        assert_eq!(encoder.as_bytes(), expected.as_bytes());
+    }
+}